@@ -0,0 +1,237 @@
+use opengl_graphics::{GlGraphics, OpenGL, Texture, TextureSettings};
+use piston_window::{RenderArgs, Key};
+use rustness::nes::palette::{Palette, PalettePreset, NtscSettings};
+use rustness::nes::ntsc::{NtscFilter, NTSC_WIDTH, NTSC_HEIGHT};
+use rustness::nes::{Nes, JoypadButton};
+use rustness::nes::region::Region;
+use rustness::nes::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT, PATTERN_TABLE_SIZE, NAMETABLES_WIDTH, NAMETABLES_HEIGHT,
+                      SPRITE_VIEW_WIDTH, SPRITE_VIEW_HEIGHT};
+
+const SCREEN_SCALE: u32 = 2;
+const SCREEN_X_Y: (u32, u32) = (0, 0);
+//Debug panels to the right of the game picture
+const NAMETABLES_X_Y: (u32, u32) = (620, 0);
+const PATTERN_TABLE_0_X_Y: (u32, u32) = (620, 490);
+const PATTERN_TABLE_1_X_Y: (u32, u32) = (756, 490);
+const PALETTE_RAM_X_Y: (u32, u32) = (892, 490);
+const PALETTE_SWATCH_SIZE: u32 = 12;
+//Sprite list below the game picture: 5 columns of 13 sprites, each with its attributes next to it
+const OAM_X_Y: (u32, u32) = (0, 490);
+const OAM_ENTRY_SIZE: (u32, u32) = (122, 17);
+const OAM_ROWS: u32 = 13;
+const TEXT_COLOR: im::Rgba<u8> = im::Rgba([255, 255, 255, 255]);
+const SCROLL_WINDOW_COLOR: im::Rgba<u8> = im::Rgba([255, 0, 255, 255]);
+
+pub struct Frontend {
+    gl: GlGraphics,
+    canvas: im::ImageBuffer<im::Rgba<u8>, Vec<u8>>,
+    texture: Texture,
+    palette: Palette,
+    palette_preset: PalettePreset,
+    ntsc: NtscFilter,
+    ntsc_enabled: bool,
+    region: Region,
+    //Palette (0-3 background, 4-7 sprites) the pattern table panels are drawn with
+    pattern_table_palette: u8,
+}
+
+impl Frontend {
+    pub fn new(opengl: OpenGL, (width, height): (u32, u32), region: Region) -> Frontend {
+        let canvas = im::ImageBuffer::new(width, height);
+        let texture = Texture::from_image(&canvas, &TextureSettings::new());
+        Frontend {
+            gl: GlGraphics::new(opengl),
+            canvas,
+            texture,
+            palette: Palette::from_preset(region.default_palette()),
+            palette_preset: region.default_palette(),
+            ntsc: NtscFilter::new(NtscSettings::default()),
+            ntsc_enabled: false,
+            region,
+            pattern_table_palette: 0,
+        }
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    pub fn next_palette_preset(&mut self) {
+        self.palette_preset = self.palette_preset.next();
+        self.palette = Palette::from_preset(self.palette_preset);
+    }
+
+    pub fn toggle_ntsc(&mut self) {
+        //The filter only knows the NTSC signal, PAL and Dendy consoles output something else
+        if self.region != Region::Ntsc {
+            return;
+        }
+        self.ntsc_enabled = !self.ntsc_enabled;
+        //The filtered image is wider, clear what the plain one leaves behind
+        for pixel in self.canvas.pixels_mut() {
+            *pixel = im::Rgba([0, 0, 0, 255]);
+        }
+    }
+
+    pub fn next_pattern_table_palette(&mut self) {
+        self.pattern_table_palette = (self.pattern_table_palette + 1) % 8;
+    }
+
+    pub fn render(&mut self, nes: &Nes, r: RenderArgs) {
+        let frame = nes.get_frame();
+        if self.ntsc_enabled {
+            //The filter already widens the picture, so only the height gets scaled
+            let filtered = self.ntsc.apply(frame, nes.get_frame_phase()).to_vec();
+            for y in 0..NTSC_HEIGHT {
+                for x in 0..NTSC_WIDTH {
+                    let (r, g, b) = filtered[y * NTSC_WIDTH + x];
+                    self.put_block(SCREEN_X_Y.0 + x as u32, SCREEN_X_Y.1 + y as u32 * SCREEN_SCALE,
+                                   (1, SCREEN_SCALE), im::Rgba([r, g, b, 255]));
+                }
+            }
+        } else {
+            self.draw_buffer(frame, SCREEN_WIDTH, SCREEN_HEIGHT, SCREEN_X_Y, SCREEN_SCALE);
+        }
+        self.draw_debug_panels(nes);
+        self.texture.update(&self.canvas);
+
+        let c = self.gl.draw_begin(r.viewport());
+        graphics::clear([0.0, 0.0, 0.0, 1.0], &mut self.gl);
+
+        graphics::image(&self.texture, c.transform, &mut self.gl);
+
+        self.gl.draw_end();
+    }
+
+    fn draw_debug_panels(&mut self, nes: &Nes) {
+        let state = nes.get_ppu_state();
+        self.draw_buffer(&state.nametable_image, NAMETABLES_WIDTH, NAMETABLES_HEIGHT, NAMETABLES_X_Y, 1);
+        self.draw_scroll_window(state.scroll_origin());
+
+        self.draw_buffer(&state.render_pattern_table(0, self.pattern_table_palette),
+                         PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE, PATTERN_TABLE_0_X_Y, 1);
+        self.draw_buffer(&state.render_pattern_table(1, self.pattern_table_palette),
+                         PATTERN_TABLE_SIZE, PATTERN_TABLE_SIZE, PATTERN_TABLE_1_X_Y, 1);
+
+        //Background palettes on the top row, sprite palettes below
+        for (i, color_no) in state.palette_ram.iter().enumerate() {
+            let (r, g, b) = self.palette.get_rgb_color(*color_no as u16);
+            let x = PALETTE_RAM_X_Y.0 + (i as u32 % 16) * PALETTE_SWATCH_SIZE;
+            let y = PALETTE_RAM_X_Y.1 + (i as u32 / 16) * PALETTE_SWATCH_SIZE;
+            self.put_block(x, y, (PALETTE_SWATCH_SIZE, PALETTE_SWATCH_SIZE), im::Rgba([r, g, b, 255]));
+        }
+
+        for (sprite, entry) in state.oam.chunks(4).enumerate() {
+            let x = OAM_X_Y.0 + (sprite as u32 / OAM_ROWS) * OAM_ENTRY_SIZE.0;
+            let y = OAM_X_Y.1 + (sprite as u32 % OAM_ROWS) * OAM_ENTRY_SIZE.1;
+            self.put_block(x, y, OAM_ENTRY_SIZE, im::Rgba([0, 0, 0, 255]));
+            self.draw_buffer(&state.render_sprite(sprite), SPRITE_VIEW_WIDTH, SPRITE_VIEW_HEIGHT, (x, y), 1);
+            //Number, x, y, tile, palette, in front of (F) or behind (B) the background, flips
+            let attr = entry[2];
+            let text = format!("{:02} X{:3} Y{:3} T{:02X} P{} {} {}{}", sprite, entry[3], entry[0], entry[1],
+                               4 + (attr & 0b11),
+                               if attr & 0b00100000 > 0 { 'B' } else { 'F' },
+                               if attr & 0b01000000 > 0 { 'H' } else { '-' },
+                               if attr & 0b10000000 > 0 { 'V' } else { '-' });
+            self.draw_text(&text, (x + SPRITE_VIEW_WIDTH as u32 + 2, y + 5));
+        }
+    }
+
+    fn draw_text(&mut self, text: &str, (x, y): (u32, u32)) {
+        for (i, c) in text.chars().enumerate() {
+            for (row, bits) in glyph(c).iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (0b100 >> col) > 0 {
+                        self.put_block(x + i as u32 * 4 + col, y + row as u32, (1, 1), TEXT_COLOR);
+                    }
+                }
+            }
+        }
+    }
+
+    fn draw_scroll_window(&mut self, (scroll_x, scroll_y): (u32, u32)) {
+        //Outlines the visible 256x240 part of the nametables, wrapping around the edges like the PPU does
+        let width = SCREEN_WIDTH as u32;
+        let height = SCREEN_HEIGHT as u32;
+        for i in 0..width {
+            let x = (scroll_x + i) % NAMETABLES_WIDTH as u32;
+            self.put_block(NAMETABLES_X_Y.0 + x, NAMETABLES_X_Y.1 + scroll_y % NAMETABLES_HEIGHT as u32,
+                           (1, 1), SCROLL_WINDOW_COLOR);
+            self.put_block(NAMETABLES_X_Y.0 + x, NAMETABLES_X_Y.1 + (scroll_y + height - 1) % NAMETABLES_HEIGHT as u32,
+                           (1, 1), SCROLL_WINDOW_COLOR);
+        }
+        for i in 0..height {
+            let y = (scroll_y + i) % NAMETABLES_HEIGHT as u32;
+            self.put_block(NAMETABLES_X_Y.0 + scroll_x % NAMETABLES_WIDTH as u32, NAMETABLES_X_Y.1 + y,
+                           (1, 1), SCROLL_WINDOW_COLOR);
+            self.put_block(NAMETABLES_X_Y.0 + (scroll_x + width - 1) % NAMETABLES_WIDTH as u32, NAMETABLES_X_Y.1 + y,
+                           (1, 1), SCROLL_WINDOW_COLOR);
+        }
+    }
+
+    fn draw_buffer(&mut self, buffer: &[u16], width: usize, height: usize,
+                   (start_x, start_y): (u32, u32), scale: u32) {
+        //Converts palette indices to colours and blows every pixel up to a scale x scale block
+        for y in 0..height {
+            for x in 0..width {
+                let (r, g, b) = self.palette.get_rgb_color(buffer[y * width + x]);
+                self.put_block(start_x + x as u32 * scale, start_y + y as u32 * scale,
+                               (scale, scale), im::Rgba([r, g, b, 255]));
+            }
+        }
+    }
+
+    fn put_block(&mut self, x: u32, y: u32, (width, height): (u32, u32), color: im::Rgba<u8>) {
+        for target_y in y..y + height {
+            for target_x in x..x + width {
+                if target_x < self.canvas.width() && target_y < self.canvas.height() {
+                    self.canvas.put_pixel(target_x, target_y, color);
+                }
+            }
+        }
+    }
+}
+
+fn glyph(c: char) -> [u8; 5] {
+    //3x5 pixel font with just what the debug panels print, one row of 3 bits per byte
+    match c {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        _ => [0; 5],
+    }
+}
+
+pub fn joypad_button(key: Key) -> Option<JoypadButton> {
+    match key {
+        Key::Right => Some(JoypadButton::Right),
+        Key::Left => Some(JoypadButton::Left),
+        Key::Down => Some(JoypadButton::Down),
+        Key::Up => Some(JoypadButton::Up),
+        Key::S => Some(JoypadButton::Start),
+        Key::A => Some(JoypadButton::Select),
+        Key::Z => Some(JoypadButton::B),
+        Key::X => Some(JoypadButton::A),
+        _ => None,
+    }
+}
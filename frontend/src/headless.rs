@@ -0,0 +1,61 @@
+extern crate image as im;
+
+use rustness::nes::Nes;
+use rustness::nes::palette::{Palette, NtscSettings};
+use rustness::nes::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use std::env;
+
+const USAGE: &str = "usage: rustness-headless <rom> [frames] [out.png] [palette.pal]\n       \
+    rustness-headless --export-pal <out.pal> [--pal] [--hue deg] [--saturation x] [--contrast x] \
+    [--brightness x] [--gamma x]";
+
+fn main() {
+    //Runs a rom with no window and saves the last frame, or generates a palette and saves it as a .pal file
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "--export-pal") {
+        export_palette(&args);
+        return;
+    }
+    let rom_path = args.first().expect(USAGE);
+    let frames: u32 = args.get(1).map(|s| s.parse().unwrap()).unwrap_or(60);
+    let out_path = args.get(2).map(|s| s.as_str()).unwrap_or("frame.png");
+
+    let mut n = Nes::new(rom_path);
+    let palette = match args.get(3) {
+        Some(palette_path) => Palette::from_file(palette_path).unwrap(),
+        None => Palette::from_preset(n.get_region().default_palette()),
+    };
+    for _ in 0..frames {
+        n.run_frame();
+    }
+
+    let frame = n.get_frame();
+    let image = im::RgbImage::from_fn(SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, |x, y| {
+        let (r, g, b) = palette.get_rgb_color(frame[y as usize * SCREEN_WIDTH + x as usize]);
+        im::Rgb([r, g, b])
+    });
+    image.save(out_path).unwrap();
+    println!("Ran {} frames, saved the last one to {}", frames, out_path);
+}
+
+fn export_palette(args: &[String]) {
+    //Decodes the composite signal with the given TV settings, like the NtscGenerated/PalGenerated presets do
+    let value = |flag: &str| args.iter().position(|arg| arg == flag).map(|i| args.get(i + 1).expect(USAGE));
+    let number = |flag: &str, default: f32| value(flag).map_or(default, |s| s.parse().expect(USAGE));
+    let defaults = NtscSettings::default();
+    let settings = NtscSettings {
+        hue: number("--hue", defaults.hue),
+        saturation: number("--saturation", defaults.saturation),
+        contrast: number("--contrast", defaults.contrast),
+        brightness: number("--brightness", defaults.brightness),
+        gamma: number("--gamma", defaults.gamma),
+    };
+    let palette = if args.iter().any(|arg| arg == "--pal") {
+        Palette::generate_pal(&settings)
+    } else {
+        Palette::generate_ntsc(&settings)
+    };
+    let out_path = value("--export-pal").unwrap();
+    palette.save(out_path).unwrap();
+    println!("Saved a palette generated with {:?} to {}", settings, out_path);
+}
@@ -0,0 +1,82 @@
+extern crate piston_window;
+extern crate image as im;
+mod frontend;
+
+use piston_window::*;
+use rustness::nes::Nes;
+use crate::frontend::{Frontend, joypad_button};
+use rustness::nes::palette::Palette;
+use std::env;
+use std::fs::File;
+use std::io::{BufReader, BufRead};
+use std::borrow::BorrowMut;
+
+fn main() {
+    let opengl = OpenGL::V4_5;
+
+    let (width, height) = (1280, 720);
+    let mut window: PistonWindow =
+        WindowSettings::new("Rustness!", (width, height))
+            .exit_on_esc(true)
+            .graphics_api(opengl)
+            .build()
+            .unwrap();
+
+    //Initialize the emulator (usage: rustness [rom] [palette.pal])
+    let args: Vec<String> = env::args().collect();
+    let rom_path = args.get(1).map(|s| s.as_str()).unwrap_or("./roms/lode.nes");
+    let mut n = Nes::new(rom_path);
+    let mut frontend = Frontend::new(opengl, (width, height), n.get_region());
+    if let Some(palette_path) = args.get(2) {
+        frontend.set_palette(Palette::from_file(palette_path).unwrap());
+    }
+
+    //TODO: REMOVE THIS TESTING CODE
+//    let file = File::open("./roms/nestest.log.txt").unwrap();
+//    let reader = BufReader::new(file);
+//    let mut lines_iter = reader.lines().map(|l| l.unwrap());
+//    let mut line_number = 1;
+    //END OF TODO
+
+    //Loop through window events, one update per frame of the rom's region (60 or 50 per second)
+    window.set_ups(n.get_region().frame_rate().round() as u64);
+
+
+    while let Some(event) = window.next() {
+        if let Some(r) = event.render_args() {
+            frontend.render(&n, r);
+        }
+
+        if let Some(u) = event.update_args() {
+            n.run_frame();
+//            assert_eq!(n.cpu.log_string, lines_iter.next().unwrap());
+//            println!("Line {:?} is okay.", line_number);
+//            line_number += 1;
+        }
+
+        if let Some(Button::Keyboard(Key::P)) = event.press_args() {
+            //Cycle through the built-in palettes
+            frontend.next_palette_preset();
+        }
+        if let Some(Button::Keyboard(Key::N)) = event.press_args() {
+            //Toggle the NTSC composite filter
+            frontend.toggle_ntsc();
+        }
+        if let Some(Button::Keyboard(Key::C)) = event.press_args() {
+            //Cycle the palette the pattern tables are shown with
+            frontend.next_pattern_table_palette();
+        }
+        if let Some(Button::Keyboard(k)) = event.press_args() {
+            //Send key presses to the game
+            if let Some(button) = joypad_button(k) {
+                n.button_press(button);
+            }
+        }
+        if let Some(Button::Keyboard(k)) = event.release_args() {
+            //Send lif to game
+            if let Some(button) = joypad_button(k) {
+                n.button_lift(button);
+            }
+        }
+    }
+}
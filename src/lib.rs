@@ -0,0 +1,12 @@
+//NES emulator core: everything needed to run a rom, with no dependencies at all.
+// The piston window and the headless runner live in the frontend crate
+pub mod nes;
+
+pub use crate::nes::{Nes, JoypadButton};
+pub use crate::nes::cpu::{Cpu, CpuVariant};
+pub use crate::nes::ppu::{Ppu, PpuState};
+pub use crate::nes::mem::Mem;
+pub use crate::nes::bus::Bus;
+pub use crate::nes::cartridge::Cartridge;
+pub use crate::nes::region::Region;
+pub use crate::nes::palette::{Palette, PalettePreset};
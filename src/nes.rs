@@ -1,34 +1,60 @@
 pub mod cpu;
 pub mod ppu;
 pub mod palette;
+pub mod ntsc;
 pub mod mem;
+pub mod bus;
+pub mod cartridge;
+pub mod region;
 
 use crate::nes::cpu::Cpu;
-use crate::nes::ppu::Ppu;
+use crate::nes::ppu::{Ppu, PpuState};
 use crate::nes::mem::Mem;
+use crate::nes::cartridge::Cartridge;
+use crate::nes::region::Region;
 use std::fs;
 use std::io::Read;
-use std::cell::RefCell;
-use std::rc::Rc;
-use piston_window::PistonWindow;
-use opengl_graphics::OpenGL;
-use piston::input::Key;
 
 
+//Joypad 1 buttons, numbered by their bit in the controller's shift register
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JoypadButton {
+    Right = 0,
+    Left = 1,
+    Down = 2,
+    Up = 3,
+    Start = 4,
+    Select = 5,
+    B = 6,
+    A = 7,
+}
+
 pub struct Nes {
     rom_header: Vec<u8>,
-    cpu: Cpu,
-    ppu: Ppu,
-    pub mem: Rc<RefCell<Mem>>,
+    region: Region,
+    pub cpu: Cpu<Mem>,
 }
 
 impl Nes {
-    pub fn new(filepath: &str, window: &mut PistonWindow, opengl: OpenGL,
-               (width, height): (u32, u32)) -> Nes {
-        //Load in the game rom and return the emulator
+    pub fn new(filepath: &str) -> Nes {
+        //Load in the game rom and return the emulator, in the region the rom asks for
+        let rom_bytes = Nes::read_rom(filepath);
+        let region = Region::detect(&rom_bytes[..16], filepath);
+        Nes::from_rom_bytes(&rom_bytes, region)
+    }
+
+    pub fn with_region(filepath: &str, region: Region) -> Nes {
+        Nes::from_rom_bytes(&Nes::read_rom(filepath), region)
+    }
+
+    fn read_rom(filepath: &str) -> Vec<u8> {
         let mut file = fs::File::open(filepath).unwrap();
         let mut rom_bytes: Vec<u8> = vec![];
         file.read_to_end(&mut rom_bytes).unwrap();
+        rom_bytes
+    }
+
+    fn from_rom_bytes(rom_bytes: &[u8], region: Region) -> Nes {
 
         //Split INES file into header and rom bytes
         // (there are also CHR rom data there, but we don't really care about them right now)
@@ -38,52 +64,149 @@ impl Nes {
         let chr_length = rom_header_bytes[5] as u64 * 8192;
         let (pgr_rom, chr_rom_and_rest) = rom.split_at(pgr_length as usize);
         let (chr_rom, rest) = chr_rom_and_rest.split_at(chr_length as usize);
-        let mem = Rc::new(RefCell::new(
-            Mem::new(pgr_rom.to_vec(), chr_rom.to_vec())
-        ));
+        let cartridge = Cartridge::new(pgr_rom.to_vec(), chr_rom.to_vec());
+        let ppu = Ppu::new(region);
 
         Nes {
             rom_header: rom_header_bytes.to_vec(),
-            mem: Rc::clone(&mem),
-            cpu: Cpu::new(&mem),
-            ppu: Ppu::new(&mem, window, opengl, (width, height)),
+            region,
+            cpu: Cpu::new(Mem::new(cartridge, ppu)),
         }
     }
 
-    pub fn emulate_frame(&mut self) {
-        //Emulate a fixed amount of cycles pef frame (roughly 1,79 / 60)
-        let mut i: i32 = 29829;
-        while i > 0 {
-//            #[cfg(debug_assertions)]
-//            println!("{:?}", self.cpu);
-            let cycles_taken = self.cpu.emulate();
-            self.ppu.emulate(cycles_taken * 3);
-            i -= cycles_taken as i32;
+    pub fn run_frame(&mut self) {
+        //Runs until the PPU finishes the picture (the start of vblank), so frames never drift
+        // against it. Instructions aren't split, so this overshoots by a few cycles at most
+        let frame = self.cpu.bus.ppu.get_frame_count();
+        while self.cpu.bus.ppu.get_frame_count() == frame {
+            self.cpu.emulate();
         }
-//        println!("LOOP!");
+        self.refresh_debug_views();
     }
 
-    pub fn render_frame(&mut self, r: piston_window::RenderArgs) {
-        self.ppu.render(r);
-    }
-    pub fn button_press(&mut self, k: Key) {
-        self.button(k, true);
+    pub fn step_instruction(&mut self) -> u8 {
+        //Runs one instruction (or interrupt) and returns the CPU cycles it took
+        let cycles = self.cpu.emulate();
+        self.refresh_debug_views();
+        cycles
     }
-    pub fn button_lift(&mut self, k: Key) {
-        self.button(k, false);
+
+    pub fn step_scanline(&mut self) {
+        let scanline = self.cpu.bus.ppu.get_scanline();
+        while self.cpu.bus.ppu.get_scanline() == scanline {
+            self.cpu.emulate();
+        }
+        self.refresh_debug_views();
     }
-    fn button(&mut self, k: Key, set: bool) {
-        match k {
-            Key::Right => {self.mem.borrow_mut().button_set(0, set)}
-            Key::Left => {self.mem.borrow_mut().button_set(1, set)}
-            Key::Down => {self.mem.borrow_mut().button_set(2, set)}
-            Key::Up => {self.mem.borrow_mut().button_set(3, set)}
-            Key::S => {self.mem.borrow_mut().button_set(4, set)}
-            Key::A => {self.mem.borrow_mut().button_set(5, set)}
-            Key::Z => {self.mem.borrow_mut().button_set(6, set)}
-            Key::X => {self.mem.borrow_mut().button_set(7, set)}
-            _ => {}
+
+    pub fn run_cycles(&mut self, cycles: u64) -> u64 {
+        //Runs whole instructions until at least `cycles` CPU cycles have passed, returns how many did
+        let mut ran = 0;
+        while ran < cycles {
+            ran += self.cpu.emulate() as u64;
         }
+        self.refresh_debug_views();
+        ran
+    }
+
+    fn refresh_debug_views(&mut self) {
+        //Done after running rather than when asked for, so get_ppu_state can take &self
+        let mem = &mut self.cpu.bus;
+        mem.ppu.refresh_nametable_image(&mem.cartridge);
+    }
+
+    pub fn get_region(&self) -> Region {
+        self.region
+    }
+
+    pub fn peek_u8(&self, addr: u16) -> u8 {
+        self.cpu.bus.peek_u8(addr)
+    }
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        let mem = &self.cpu.bus;
+        mem.ppu.peek_vram(&mem.cartridge, addr)
+    }
+
+    pub fn get_frame(&self) -> &[u16] {
+        self.cpu.bus.ppu.get_frame()
+    }
+    pub fn get_frame_phase(&self) -> usize {
+        self.cpu.bus.ppu.get_frame_phase()
+    }
+    pub fn get_ppu_state(&self) -> PpuState {
+        let mem = &self.cpu.bus;
+        mem.ppu.get_state(&mem.cartridge)
+    }
+    pub fn button_press(&mut self, button: JoypadButton) {
+        self.cpu.bus.button_set(button as u8, true);
+    }
+    pub fn button_lift(&mut self, button: JoypadButton) {
+        self.cpu.bus.button_set(button as u8, false);
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    //blargg's ppu_vbl_nmi singles (https://github.com/christopherpow/nes-test-roms), which check the vblank flag
+    // and NMI timing down to the dot. They aren't in the repo, fetch them before running the ignored tests:
+    //   mkdir -p roms/ppu_vbl_nmi && for t in 01-vbl_basics 02-vbl_set_time 03-vbl_clear_time 04-nmi_control \
+    //     05-nmi_timing 06-suppression 07-nmi_on_timing 08-nmi_off_timing 09-even_odd_frames 10-even_odd_timing; do
+    //     curl -Lo roms/ppu_vbl_nmi/$t.nes \
+    //       https://github.com/christopherpow/nes-test-roms/raw/master/ppu_vbl_nmi/rom_singles/$t.nes; done
+    //   cargo test -- --ignored
+    const PPU_VBL_NMI_PATH: &str = "./roms/ppu_vbl_nmi";
+    const PPU_VBL_NMI_TESTS: [&str; 10] = ["01-vbl_basics", "02-vbl_set_time", "03-vbl_clear_time", "04-nmi_control",
+        "05-nmi_timing", "06-suppression", "07-nmi_on_timing", "08-nmi_off_timing", "09-even_odd_frames",
+        "10-even_odd_timing"];
+    //The tests report through PRG-RAM: a status byte at $6000 ($80 while running, $81 when they want a reset,
+    // the result code otherwise), a signature at $6001-$6003 and a zero terminated text from $6004
+    const BLARGG_STATUS: u16 = 0x6000;
+    const BLARGG_SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+    const BLARGG_TEXT: u16 = 0x6004;
+    const BLARGG_RUNNING: u8 = 0x80;
+    const BLARGG_NEEDS_RESET: u8 = 0x81;
+    const BLARGG_MAX_FRAMES: u32 = 60 * 60;
+    //A Nes is too big for the default test thread stack in debug builds
+    const TEST_STACK_SIZE: usize = 64 * 1024 * 1024;
+
+    fn blargg_text(nes: &Nes) -> String {
+        (BLARGG_TEXT..0x8000).map(|addr| nes.peek_u8(addr)).take_while(|c| *c != 0).map(|c| c as char).collect()
+    }
+
+    fn run_blargg_test(path: String) -> (u8, String) {
+        let rom_bytes = fs::read(&path)
+            .unwrap_or_else(|e| panic!("Can't read {} ({}), see PPU_VBL_NMI_PATH for where to get it", path, e));
+        thread::Builder::new().stack_size(TEST_STACK_SIZE).spawn(move || {
+            let mut nes = Nes::from_rom_bytes(&rom_bytes, Region::Ntsc);
+            for _ in 0..BLARGG_MAX_FRAMES {
+                nes.run_frame();
+                let signature = [nes.peek_u8(BLARGG_STATUS + 1), nes.peek_u8(BLARGG_STATUS + 2),
+                    nes.peek_u8(BLARGG_STATUS + 3)];
+                let status = nes.peek_u8(BLARGG_STATUS);
+                if signature == BLARGG_SIGNATURE && status != BLARGG_RUNNING {
+                    return (status, blargg_text(&nes));
+                }
+            }
+            (BLARGG_RUNNING, blargg_text(&nes))
+        }).unwrap().join().unwrap()
+    }
+
+    #[test]
+    #[ignore]
+    fn blargg_ppu_vbl_nmi() {
+        let mut failures = vec![];
+        for test in PPU_VBL_NMI_TESTS.iter() {
+            let (status, text) = run_blargg_test(format!("{}/{}.nes", PPU_VBL_NMI_PATH, test));
+            match status {
+                0 => (),
+                BLARGG_RUNNING => failures.push(format!("{}: did not finish\n{}", test, text)),
+                BLARGG_NEEDS_RESET => failures.push(format!("{}: asks for a reset, which isn't emulated", test)),
+                code => failures.push(format!("{}: failed with code {}\n{}", test, code, text)),
+            }
+        }
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+}
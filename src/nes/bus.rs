@@ -0,0 +1,25 @@
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, val: u8);
+    //Same as read, but without any side effects (for debuggers and other tools)
+    fn peek(&self, addr: u16) -> u8;
+    //Lets the rest of the system catch up after the CPU spent `cycles` cycles
+    fn tick(&mut self, _cycles: u8) {}
+
+    //Interrupt lines - a plain RAM bus never raises any of them
+    fn irq_pending(&mut self) -> bool {
+        false
+    }
+    fn acknowledge_irq(&mut self) {}
+    fn nmi_pending(&mut self) -> bool {
+        false
+    }
+    fn acknowledge_nmi(&mut self) {}
+
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lower_byte = self.read(addr);
+        let upper_byte = self.read(addr.wrapping_add(1));
+
+        (lower_byte as u16) | ((upper_byte as u16) << 8)
+    }
+}
@@ -0,0 +1,105 @@
+use crate::nes::ppu::Tile;
+use std::sync::Arc;
+
+//Boards without CHR ROM have 8KB of CHR RAM instead
+const CHR_RAM_SIZE: usize = 0x2000;
+//8KB of (battery backed on some boards) work RAM at $6000-$7FFF
+const PRG_RAM_SIZE: usize = 0x2000;
+const CHR_TILE_COUNT: usize = 512;
+
+pub struct Cartridge {
+    pgr_rom: Vec<u8>,
+    prg_ram: Vec<u8>,
+    chr: Vec<u8>,
+    chr_is_ram: bool,
+    //Decoded copies of the 512 tiles in $0000-$1FFF, redone as soon as their bytes change.
+    // Shared with the PPU snapshots, which only get a copy of their own once CHR RAM changes under them
+    chr_tiles: Arc<Vec<Tile>>,
+    //Bumped whenever everything in $0000-$1FFF changes at once, for caches built from the tiles
+    chr_generation: u64,
+}
+
+impl Cartridge {
+    pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>) -> Cartridge {
+        let chr_is_ram = chr_rom.is_empty();
+        let mut cartridge = Cartridge {
+            pgr_rom,
+            prg_ram: vec![0; PRG_RAM_SIZE],
+            chr: if chr_is_ram { vec![0; CHR_RAM_SIZE] } else { chr_rom },
+            chr_is_ram,
+            chr_tiles: Arc::new(vec![[[0; 8]; 8]; CHR_TILE_COUNT]),
+            chr_generation: 0,
+        };
+        cartridge.invalidate_chr_tiles();
+        cartridge
+    }
+
+    pub fn read_prg(&mut self, addr: u16) -> u8 {
+        self.peek_prg(addr)
+    }
+
+    pub fn peek_prg(&self, addr: u16) -> u8 {
+        //NROM-128 mirrors its single 16KB bank into both halves of $8000-$FFFF
+        let real_addr = (addr - 0x8000) as usize % self.pgr_rom.len();
+        self.pgr_rom[real_addr]
+    }
+
+    pub fn read_prg_ram(&mut self, addr: u16) -> u8 {
+        self.peek_prg_ram(addr)
+    }
+
+    pub fn peek_prg_ram(&self, addr: u16) -> u8 {
+        self.prg_ram[(addr - 0x6000) as usize]
+    }
+
+    pub fn write_prg_ram(&mut self, addr: u16, val: u8) {
+        self.prg_ram[(addr - 0x6000) as usize] = val;
+    }
+
+    pub fn read_chr(&mut self, addr: u16) -> u8 {
+        self.peek_chr(addr)
+    }
+
+    pub fn peek_chr(&self, addr: u16) -> u8 {
+        self.chr[addr as usize]
+    }
+
+    pub fn write_chr(&mut self, addr: u16, val: u8) -> bool {
+        //Writes to CHR ROM go nowhere. Returns whether the tile at addr changed
+        if !self.chr_is_ram || self.chr[addr as usize] == val {
+            return false;
+        }
+        self.chr[addr as usize] = val;
+        let tile = self.decode_tile(addr >> 4);
+        Arc::make_mut(&mut self.chr_tiles)[(addr >> 4) as usize] = tile;
+        true
+    }
+
+    pub fn invalidate_chr_tiles(&mut self) {
+        //Whenever what's mapped into $0000-$1FFF changes as a whole (e.g. a CHR bank switch)
+        let tiles: Vec<Tile> = (0..CHR_TILE_COUNT as u16).map(|tile_no| self.decode_tile(tile_no)).collect();
+        self.chr_tiles = Arc::new(tiles);
+        self.chr_generation += 1;
+    }
+
+    pub fn get_chr_tiles(&self) -> &Arc<Vec<Tile>> {
+        &self.chr_tiles
+    }
+
+    pub fn get_chr_generation(&self) -> u64 {
+        self.chr_generation
+    }
+
+    fn decode_tile(&self, tile_no: u16) -> Tile {
+        //Each tile is 16 bytes: 8 rows of low bits followed by 8 rows of high bits
+        let mut tile = [[0; 8]; 8];
+        for (row, pixels) in tile.iter_mut().enumerate() {
+            let low_bits = self.peek_chr((tile_no << 4) + row as u16);
+            let high_bits = self.peek_chr((tile_no << 4) + row as u16 + 8);
+            for (col, pixel) in pixels.iter_mut().enumerate() {
+                *pixel = ((low_bits >> (7 - col)) & 0b1) | (((high_bits >> (7 - col)) & 0b1) << 1);
+            }
+        }
+        tile
+    }
+}
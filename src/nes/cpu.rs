@@ -1,9 +1,15 @@
 use std::fmt;
-use crate::nes::Mem;
-use std::rc::Rc;
-use std::cell::RefCell;
+use crate::nes::bus::Bus;
 
-pub struct Cpu {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CpuVariant {
+    //NES CPU - the D flag can be set, but arithmetic always stays binary
+    Ricoh2A03,
+    //Stock NMOS 6502 (Apple II, C64...) with working BCD arithmetic
+    Nmos6502,
+}
+
+pub struct Cpu<B: Bus> {
     pub pc: u16,
     pub a: u8,
     pub x: u8,
@@ -11,28 +17,62 @@ pub struct Cpu {
     pub s: u8,
     pub p: u8,
     pub cycles: u64,
-    pub mem: Rc<RefCell<Mem>>,
+    pub variant: CpuVariant,
+    pub log_string: String,
+    pub bus: B,
+    ticked_cycles: u8,
 }
 
-impl Cpu {
-    pub fn new(mem: &Rc<RefCell<Mem>>) -> Cpu {
-        let pc = mem.borrow_mut().read_u16(0xFFFC);
-        Cpu { pc, a: 0, x: 0, y: 0, s: 0xFD, p: 0x24, mem: Rc::clone(mem), cycles: 7 }
+impl<B: Bus> Cpu<B> {
+    pub fn new(mut bus: B) -> Cpu<B> {
+        let pc = bus.read_u16(0xFFFC);
+        Cpu { pc, a: 0, x: 0, y: 0, s: 0xFD, p: 0x24, bus, cycles: 7,
+            variant: CpuVariant::Ricoh2A03, log_string: "".to_string(), ticked_cycles: 0 }
     }
 
-    pub fn log_me(&self, opcode: u8) {
-        self.mem.borrow_mut().log_string = format!(
+    pub fn log_me(&mut self, opcode: u8) {
+        self.log_string = format!(
             "{:04X} | {:02X} | A:{:02X} | X:{:02X} | Y:{:02X} | P:{:02X} | SP:{:02X} | CYC:{:?}",
             self.pc, opcode, self.a, self.x, self.y, self.p, self.s, self.cycles
         );
     }
 
     pub fn emulate(&mut self) -> u8 {
+        self.ticked_cycles = 0;
         let cycles = self.run_next_opcode();
         self.cycles += cycles as u64;
+        //Catch up on the cycles that didn't touch memory
+        if cycles > self.ticked_cycles {
+            self.bus.tick(cycles - self.ticked_cycles);
+        }
         cycles
     }
 
+    //Every memory access takes one cycle, so the rest of the system is ticked right after it
+    // and sees register accesses on (roughly) the right cycle of the instruction
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.bus.read(addr);
+        self.tick();
+        data
+    }
+    fn write(&mut self, addr: u16, val: u8) {
+        self.bus.write(addr, val);
+        self.tick();
+    }
+    fn read_signed(&mut self, addr: u16) -> i8 {
+        self.read(addr) as i8
+    }
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lower_byte = self.read(addr);
+        let upper_byte = self.read(addr.wrapping_add(1));
+
+        (lower_byte as u16) | ((upper_byte as u16) << 8)
+    }
+    fn tick(&mut self) {
+        self.bus.tick(1);
+        self.ticked_cycles += 1;
+    }
+
     pub fn set_negative(&mut self, set: bool) {
         if set { self.p = self.p | 0b10000000; } else { self.p = self.p & 0b01111111; }
     }
@@ -70,7 +110,7 @@ impl Cpu {
         (self.p & 0b00000001) > 0
     }
     pub fn stack_push_u8(&mut self, n: u8) {
-        self.mem.borrow_mut().write_u8(self.s as u16 | 0x100, n);
+        self.write(self.s as u16 | 0x100, n);
         self.s = self.s.wrapping_sub(1);
     }
     pub fn stack_push_u16(&mut self, n: u16) {
@@ -79,7 +119,7 @@ impl Cpu {
     }
     pub fn stack_pop_u8(&mut self) -> u8 {
         self.s = self.s.wrapping_add(1);
-        self.mem.borrow_mut().read_u8(self.s as u16 | 0x100)
+        self.read(self.s as u16 | 0x100)
     }
     pub fn stack_pop_u16(&mut self) -> u16 {
         let lower = self.stack_pop_u8() as u16;
@@ -88,7 +128,7 @@ impl Cpu {
     }
     pub fn branch_if(&mut self, branch: bool) -> u8 {
         if branch {
-            let offset = self.mem.borrow_mut().read_signed(self.pc);
+            let offset = self.read_signed(self.pc);
             self.pc = self.pc.wrapping_add(1);
             let old_page = self.pc >> 8;
             self.pc = self.pc.wrapping_add(offset as u16);
@@ -105,21 +145,20 @@ impl Cpu {
     }
 
     pub fn get_indirect_x_addr(&mut self) -> u16 {
-        let adr_of_adr_base = self.mem.borrow_mut().read_u8(self.pc);
+        let adr_of_adr_base = self.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
         let adr_of_adr_full = adr_of_adr_base.wrapping_add(self.x);
-        let adr_low_byte = self.mem.borrow_mut().read_u8(adr_of_adr_full as u16);
-        let adr_high_byte = self.mem.borrow_mut().read_u8(
+        let adr_low_byte = self.read(adr_of_adr_full as u16);
+        let adr_high_byte = self.read(
             adr_of_adr_full.wrapping_add(1) as u16);
         (adr_low_byte as u16) + ((adr_high_byte as u16) << 8)
     }
 
     pub fn get_indirect_y_addr(&mut self) -> (u16, u8) {
-        let adr_of_adr_base = self.mem.borrow_mut().read_u8(self.pc);
+        let adr_of_adr_base = self.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
-        let low_byte = self.mem.borrow_mut().read_u8(adr_of_adr_base as u16);
-        let high_byte = self.mem.borrow_mut()
-            .read_u8(adr_of_adr_base.wrapping_add(1) as u16);
+        let low_byte = self.read(adr_of_adr_base as u16);
+        let high_byte = self.read(adr_of_adr_base.wrapping_add(1) as u16);
         let mut adr_of_adr_full = ((high_byte as u16) << 8) + low_byte as u16;
         adr_of_adr_full = adr_of_adr_full.wrapping_add(self.y as u16);
 
@@ -132,9 +171,9 @@ impl Cpu {
     }
 
     pub fn get_absolute_x_addr(&mut self) -> (u16, u8) {
-        let low_byte = self.mem.borrow_mut().read_u8(self.pc);
+        let low_byte = self.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
-        let high_byte = self.mem.borrow_mut().read_u8(self.pc);
+        let high_byte = self.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
         let addr_full = (((high_byte as u16) << 8) + (low_byte as u16))
             .wrapping_add(self.x as u16);
@@ -146,9 +185,9 @@ impl Cpu {
         (addr_full, additional_cycle)
     }
     pub fn get_absolute_y_addr(&mut self) -> (u16, u8) {
-        let low_byte = self.mem.borrow_mut().read_u8(self.pc);
+        let low_byte = self.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
-        let high_byte = self.mem.borrow_mut().read_u8(self.pc);
+        let high_byte = self.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
         let addr_full = (((high_byte as u16) << 8) + (low_byte as u16))
             .wrapping_add(self.y as u16);
@@ -160,7 +199,15 @@ impl Cpu {
         (addr_full, additional_cycle)
     }
 
+    fn decimal_mode_active(&mut self) -> bool {
+        self.variant == CpuVariant::Nmos6502 && self.get_decimal()
+    }
+
     pub fn adc(&mut self, n: u8) {
+        if self.decimal_mode_active() {
+            self.adc_decimal(n);
+            return;
+        }
         let mut dirty = (self.a as u16).wrapping_add(n as u16);
         let mut dirty_signed = ((self.a as i8) as i16).wrapping_add((n as i8) as i16);
         if self.get_carry() {
@@ -176,7 +223,42 @@ impl Cpu {
         self.set_overflow(a_is_signed != dirty_res_is_signed);
     }
 
+    fn adc_decimal(&mut self, n: u8) {
+        //NMOS quirks: Z comes from the binary sum, N and V from the sum after only the low
+        // nibble got adjusted, and C from the fully adjusted result
+        let carry = self.get_carry() as u16;
+        let binary = (self.a as u16) + (n as u16) + carry;
+        let mut low = ((self.a & 0x0F) as u16) + ((n & 0x0F) as u16) + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let mut res = ((self.a & 0xF0) as u16) + ((n & 0xF0) as u16) + low;
+        self.set_zero(binary & 0xFF == 0);
+        self.set_negative(res & 0x80 > 0);
+        self.set_overflow((self.a as u16 ^ res) & (n as u16 ^ res) & 0x80 > 0);
+        if res >= 0xA0 {
+            res += 0x60;
+        }
+        self.set_carry(res >= 0x100);
+        self.a = res as u8;
+    }
+
+    fn sbc_decimal_result(a: u8, n: u8, carry: bool) -> u8 {
+        //Flags on NMOS are identical to binary mode, only the accumulator gets adjusted
+        let mut low = ((a & 0x0F) as i16) - ((n & 0x0F) as i16) + (carry as i16) - 1;
+        if low < 0 {
+            low = ((low - 0x06) & 0x0F) - 0x10;
+        }
+        let mut res = ((a & 0xF0) as i16) - ((n & 0xF0) as i16) + low;
+        if res < 0 {
+            res -= 0x60;
+        }
+        res as u8
+    }
+
     pub fn sbc(&mut self, num_orig: u8) {
+        let a_before = self.a;
+        let carry_before = self.get_carry();
         let n = !num_orig;
         let mut dirty = (self.a as u16).wrapping_add(n as u16);
         let mut dirty_signed = ((self.a as i8) as i16).wrapping_add((n as i8) as i16);
@@ -191,51 +273,56 @@ impl Cpu {
         let a_is_signed = self.a >= 128;
         let dirty_res_is_signed = dirty_signed < 0;
         self.set_overflow(a_is_signed != dirty_res_is_signed);
+        if self.decimal_mode_active() {
+            self.a = Self::sbc_decimal_result(a_before, num_orig, carry_before);
+        }
     }
 
     pub fn run_next_opcode(&mut self) -> u8 {
         //Serve interrupts first
         let interrupt_disable = self.get_interrupt_disable();
-        if self.mem.borrow_mut().irq == 0 && !interrupt_disable {
+        if self.bus.irq_pending() && !interrupt_disable {
             self.set_interrupt_disable(true);
-            self.mem.borrow_mut().irq = 1;
+            self.bus.acknowledge_irq();
             self.stack_push_u16(self.pc);
             self.stack_push_u8(self.p | 0b10000);
-            self.pc = self.mem.borrow_mut().read_u16(0xFFFE);
+            self.pc = self.read_u16(0xFFFE);
             self.p = self.p & 0b11001111;
             self.p = self.p | 0b100000;
             return 7;
         }
 
-        if self.mem.borrow_mut().get_trigger_nmi() {
-            self.mem.borrow_mut().set_trigger_nmi(false);
+        if self.bus.nmi_pending() {
+            self.bus.acknowledge_nmi();
             self.set_interrupt_disable(true);
-            self.mem.borrow_mut().irq = 1;
+            self.bus.acknowledge_irq();
             self.stack_push_u16(self.pc);
             self.stack_push_u8(self.p | 0b10000);
-            self.pc = self.mem.borrow_mut().read_u16(0xFFFA);
+            self.pc = self.read_u16(0xFFFA);
             self.p = self.p & 0b11001111;
             self.p = self.p | 0b100000;
             return 7;
         }
 
         //Emulates one opcode and returns the amount of cycles one opcode took
-        let opcode = self.mem.borrow_mut().read_u8(self.pc);
+        let opcode = self.read(self.pc);
         #[cfg(debug_assertions)]
             self.log_me(opcode);
         self.pc = self.pc.wrapping_add(1);
         match opcode {
             0x0 => {
-                self.stack_push_u16(self.pc);
-                self.stack_push_u8(self.p | 0b10000);
-                self.pc = self.mem.borrow_mut().read_u16(0xFFFE);
+                //BRK skips a padding byte, so the return address is 2 bytes after the opcode
+                self.stack_push_u16(self.pc.wrapping_add(1));
+                self.stack_push_u8(self.p | 0b110000);
+                self.pc = self.read_u16(0xFFFE);
                 self.p = self.p & 0b11001111;
-                self.p = self.p | 0b10000;
+                self.p = self.p | 0b100000;
+                self.set_interrupt_disable(true);
                 7
             }
             0x1 => {
                 let adr = self.get_indirect_x_addr();
-                self.a = self.a | self.mem.borrow_mut().read_u8(adr);
+                self.a = self.a | self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 6
@@ -243,10 +330,10 @@ impl Cpu {
 //            0x2 => { 2 }
             0x3 => {
                 let adr = self.get_indirect_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -257,32 +344,32 @@ impl Cpu {
                 3
             }
             0x5 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 3
             }
             0x6 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry(n >= 128);
                 n = n << 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 5
             }
             0x7 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc) as u16;
+                let adr = self.read(self.pc) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -293,7 +380,7 @@ impl Cpu {
                 3
             }
             0x9 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
@@ -313,32 +400,32 @@ impl Cpu {
                 4
             }
             0xd => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0xe => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 6
             }
             0xf => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -350,7 +437,7 @@ impl Cpu {
             }
             0x11 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -359,10 +446,10 @@ impl Cpu {
 //            0x12 => { 18 }
             0x13 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -373,32 +460,32 @@ impl Cpu {
                 4
             }
             0x15 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0x16 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 6
             }
             0x17 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -410,7 +497,7 @@ impl Cpu {
             }
             0x19 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -421,10 +508,10 @@ impl Cpu {
             }
             0x1b => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -436,7 +523,7 @@ impl Cpu {
             }
             0x1d => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -444,27 +531,27 @@ impl Cpu {
             }
             0x1e => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 7
             }
             0x1f => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry(n >= 128);
                 n = n << 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a | n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 7
             }
             0x20 => {
-                let jmp_adr = self.mem.borrow_mut().read_u16(self.pc);
+                let jmp_adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.stack_push_u16(self.pc);
                 self.pc = jmp_adr;
@@ -472,7 +559,7 @@ impl Cpu {
             }
             0x21 => {
                 let adr = self.get_indirect_x_addr();
-                self.a = self.a & self.mem.borrow_mut().read_u8(adr);
+                self.a = self.a & self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 6
@@ -480,7 +567,7 @@ impl Cpu {
 //            0x22 => { 34 }
             0x23 => {
                 let adr = self.get_indirect_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -489,16 +576,16 @@ impl Cpu {
                 }
                 self.set_carry((n & 128) == 128);
                 n = (n << 1) | c;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 8
             }
             0x24 => {
-                let ad = self.mem.borrow_mut().read_u8(self.pc);
+                let ad = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(ad as u16);
+                let n = self.read(ad as u16);
                 let res = n & self.a;
                 self.set_zero(res == 0);
                 self.set_negative((n >> 7) > 0);
@@ -506,18 +593,18 @@ impl Cpu {
                 3
             }
             0x25 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 3
             }
             0x26 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -528,13 +615,13 @@ impl Cpu {
                 n = (n << 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 5
             }
             0x27 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc) as u16;
+                let adr = self.read(self.pc) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -543,7 +630,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 128) == 128);
                 n = (n << 1) | c;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -557,7 +644,7 @@ impl Cpu {
                 4
             }
             0x29 => {
-                let n: u8 = self.mem.borrow_mut().read_u8(self.pc);
+                let n: u8 = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
@@ -579,9 +666,9 @@ impl Cpu {
             }
 //            0x2b => { 43 }
             0x2c => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 let res = n & self.a;
                 self.set_zero(res == 0);
                 self.set_negative((n >> 7) > 0);
@@ -589,18 +676,18 @@ impl Cpu {
                 4
             }
             0x2d => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0x2e => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -611,13 +698,13 @@ impl Cpu {
                 n = (n << 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 6
             }
             0x2f => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -626,7 +713,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 128) == 128);
                 n = (n << 1) | c;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -638,7 +725,7 @@ impl Cpu {
             }
             0x31 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -647,7 +734,7 @@ impl Cpu {
 //            0x32 => { 50 }
             0x33 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -656,7 +743,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 128) == 128);
                 n = (n << 1) | c;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -667,18 +754,18 @@ impl Cpu {
                 4
             }
             0x35 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0x36 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -689,13 +776,13 @@ impl Cpu {
                 n = (n << 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 6
             }
             0x37 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -704,7 +791,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 128) == 128);
                 n = (n << 1) | c;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -716,7 +803,7 @@ impl Cpu {
             }
             0x39 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -727,7 +814,7 @@ impl Cpu {
             }
             0x3b => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -736,7 +823,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 128) == 128);
                 n = (n << 1) | c;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -748,7 +835,7 @@ impl Cpu {
             }
             0x3d => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -756,7 +843,7 @@ impl Cpu {
             }
             0x3e => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -767,12 +854,12 @@ impl Cpu {
                 n = (n << 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 7
             }
             0x3f => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 1;
@@ -781,7 +868,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 128) == 128);
                 n = (n << 1) | c;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a & n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -795,7 +882,7 @@ impl Cpu {
             }
             0x41 => {
                 let adr = self.get_indirect_x_addr();
-                self.a = self.a ^ self.mem.borrow_mut().read_u8(adr);
+                self.a = self.a ^ self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 6
@@ -803,10 +890,10 @@ impl Cpu {
 //            0x42 => { 66 }
             0x43 => {
                 let adr = self.get_indirect_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -817,32 +904,32 @@ impl Cpu {
                 3
             }
             0x45 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 3
             }
             0x46 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 5
             }
             0x47 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc) as u16;
+                let adr = self.read(self.pc) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -853,7 +940,7 @@ impl Cpu {
                 3
             }
             0x49 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
@@ -869,36 +956,36 @@ impl Cpu {
             }
 //            0x4b => { 75 }
             0x4c => {
-                self.pc = self.mem.borrow_mut().read_u16(self.pc);
+                self.pc = self.read_u16(self.pc);
                 3
             }
             0x4d => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0x4e => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 6
             }
             0x4f => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -910,7 +997,7 @@ impl Cpu {
             }
             0x51 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -919,10 +1006,10 @@ impl Cpu {
 //            0x52 => { 82 }
             0x53 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -933,32 +1020,32 @@ impl Cpu {
                 4
             }
             0x55 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0x56 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 6
             }
             0x57 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -967,7 +1054,7 @@ impl Cpu {
 //            0x58 => { 88 }
             0x59 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -978,10 +1065,10 @@ impl Cpu {
             }
             0x5b => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -993,7 +1080,7 @@ impl Cpu {
             }
             0x5d => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -1001,20 +1088,20 @@ impl Cpu {
             }
             0x5e => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 7
             }
             0x5f => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 self.set_carry((n & 1) == 1);
                 n = n >> 1;
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 self.a = self.a ^ n;
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
@@ -1027,14 +1114,14 @@ impl Cpu {
             }
             0x61 => {
                 let adr = self.get_indirect_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.adc(n);
                 6
             }
 //            0x62 => { 98 }
             0x63 => {
                 let adr = self.get_indirect_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1043,7 +1130,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 1) == 1);
                 n = (n >> 1) | c;
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 self.adc(n);
                 8
             }
@@ -1052,16 +1139,16 @@ impl Cpu {
                 3
             }
             0x65 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.adc(n);
                 3
             }
             0x66 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1072,13 +1159,13 @@ impl Cpu {
                 n = (n >> 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 5
             }
             0x67 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc) as u16;
+                let adr = self.read(self.pc) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1087,7 +1174,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 1) == 1);
                 n = (n >> 1) | c;
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 self.adc(n);
                 5
             }
@@ -1098,7 +1185,7 @@ impl Cpu {
                 4
             }
             0x69 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.adc(n);
                 2
@@ -1118,28 +1205,28 @@ impl Cpu {
             }
 //            0x6b => { 107 }
             0x6c => {
-                let adr_of_adr = self.mem.borrow_mut().read_u16(self.pc);
-                let low_byte = self.mem.borrow_mut().read_u8(adr_of_adr);
+                let adr_of_adr = self.read_u16(self.pc);
+                let low_byte = self.read(adr_of_adr);
                 let high_byte: u8;
                 if (adr_of_adr & 0xFF) == 0xFF {
-                    high_byte = self.mem.borrow_mut().read_u8(adr_of_adr & 0xFF00);
+                    high_byte = self.read(adr_of_adr & 0xFF00);
                 } else {
-                    high_byte = self.mem.borrow_mut().read_u8(adr_of_adr.wrapping_add(1));
+                    high_byte = self.read(adr_of_adr.wrapping_add(1));
                 }
                 self.pc = ((high_byte as u16) << 8) | low_byte as u16;
                 5
             }
             0x6d => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.adc(n);
                 4
             }
             0x6e => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1150,13 +1237,13 @@ impl Cpu {
                 n = (n >> 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr, n);
+                self.write(adr, n);
                 6
             }
             0x6f => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1165,7 +1252,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 1) == 1);
                 n = (n >> 1) | c;
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 self.adc(n);
                 6
             }
@@ -1175,14 +1262,14 @@ impl Cpu {
             }
             0x71 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.adc(n);
                 5 + additional_cycles
             }
 //            0x72 => { 114 }
             0x73 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1191,7 +1278,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 1) == 1);
                 n = (n >> 1) | c;
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 self.adc(n);
                 8
             }
@@ -1200,16 +1287,16 @@ impl Cpu {
                 4
             }
             0x75 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.adc(n);
                 4
             }
             0x76 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1220,13 +1307,13 @@ impl Cpu {
                 n = (n >> 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 6
             }
             0x77 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1235,7 +1322,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 1) == 1);
                 n = (n >> 1) | c;
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 self.adc(n);
                 6
             }
@@ -1245,7 +1332,7 @@ impl Cpu {
             }
             0x79 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.adc(n);
                 4 + additional_cycles
             }
@@ -1254,7 +1341,7 @@ impl Cpu {
             }
             0x7b => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1263,7 +1350,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 1) == 1);
                 n = (n >> 1) | c;
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 self.adc(n);
                 7
             }
@@ -1273,13 +1360,13 @@ impl Cpu {
             }
             0x7d => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.adc(n);
                 4 + additional_cycles
             }
             0x7e => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr);
+                let mut n = self.read(adr);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1290,12 +1377,12 @@ impl Cpu {
                 n = (n >> 1) | c;
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 7
             }
             0x7f => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let mut n = self.mem.borrow_mut().read_u8(adr as u16);
+                let mut n = self.read(adr as u16);
                 let c: u8;
                 if self.get_carry() {
                     c = 128;
@@ -1304,7 +1391,7 @@ impl Cpu {
                 }
                 self.set_carry((n & 1) == 1);
                 n = (n >> 1) | c;
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                self.write(adr as u16, n);
                 self.adc(n);
                 7
             }
@@ -1314,7 +1401,7 @@ impl Cpu {
             }
             0x81 => {
                 let adr_full = self.get_indirect_x_addr();
-                self.mem.borrow_mut().write_u8(adr_full, self.a);
+                self.write(adr_full, self.a);
                 6
             }
             0x82 => {
@@ -1324,32 +1411,32 @@ impl Cpu {
             0x83 => {
                 let adr = self.get_indirect_x_addr();
                 let res = self.x & self.a;
-                self.mem.borrow_mut().write_u8(adr, res);
+                self.write(adr, res);
                 6
             }
             0x84 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr as u16, self.y);
+                self.write(adr as u16, self.y);
                 3
             }
             0x85 => {
-                let ad = self.mem.borrow_mut().read_u8(self.pc);
+                let ad = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                self.mem.borrow_mut().write_u8(ad as u16, self.a);
+                self.write(ad as u16, self.a);
                 3
             }
             0x86 => {
-                let ad = self.mem.borrow_mut().read_u8(self.pc);
+                let ad = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                self.mem.borrow_mut().write_u8(ad as u16, self.x);
+                self.write(ad as u16, self.x);
                 3
             }
             0x87 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 let res = self.x & self.a;
-                self.mem.borrow_mut().write_u8(adr as u16, res);
+                self.write(adr as u16, res);
                 3
             }
             0x88 => {
@@ -1370,28 +1457,28 @@ impl Cpu {
             }
 //            0x8b => { 139 }
             0x8c => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                self.mem.borrow_mut().write_u8(adr, self.y);
+                self.write(adr, self.y);
                 4
             }
             0x8d => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                self.mem.borrow_mut().write_u8(adr, self.a);
+                self.write(adr, self.a);
                 4
             }
             0x8e => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                self.mem.borrow_mut().write_u8(adr, self.x);
+                self.write(adr, self.x);
                 4
             }
             0x8f => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
                 let res = self.x & self.a;
-                self.mem.borrow_mut().write_u8(adr as u16, res);
+                self.write(adr as u16, res);
                 4
             }
             0x90 => {
@@ -1400,34 +1487,34 @@ impl Cpu {
             }
             0x91 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                self.mem.borrow_mut().write_u8(adr, self.a);
+                self.write(adr, self.a);
                 6
             }
 //            0x92 => { 146 }
 //            0x93 => { 147 }
             0x94 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr.wrapping_add(self.x) as u16, self.y);
+                self.write(adr.wrapping_add(self.x) as u16, self.y);
                 4
             }
             0x95 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, self.a);
+                self.write(adr, self.a);
                 4
             }
             0x96 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.y) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.y) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, self.x);
+                self.write(adr, self.x);
                 4
             }
             0x97 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.y);
+                let adr = self.read(self.pc).wrapping_add(self.y);
                 self.pc = self.pc.wrapping_add(1);
                 let res = self.x & self.a;
-                self.mem.borrow_mut().write_u8(adr as u16, res);
+                self.write(adr as u16, res);
                 4
             }
             0x98 => {
@@ -1438,7 +1525,7 @@ impl Cpu {
             }
             0x99 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                self.mem.borrow_mut().write_u8(adr, self.a);
+                self.write(adr, self.a);
                 5
             }
             0x9a => {
@@ -1449,13 +1536,13 @@ impl Cpu {
 //            0x9c => { 156 }
             0x9d => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                self.mem.borrow_mut().write_u8(adr, self.a);
+                self.write(adr, self.a);
                 5
             }
 //            0x9e => { 158 }
 //            0x9f => { 159 }
             0xa0 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.y = n;
                 self.set_zero(self.y == 0);
@@ -1464,13 +1551,13 @@ impl Cpu {
             }
             0xa1 => {
                 let adr_full = self.get_indirect_x_addr();
-                self.a = self.mem.borrow_mut().read_u8(adr_full);
+                self.a = self.read(adr_full);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 6
             }
             0xa2 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.x = n;
                 self.set_zero(self.x == 0);
@@ -1479,7 +1566,7 @@ impl Cpu {
             }
             0xa3 => {
                 let adr = self.get_indirect_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.x = n;
                 self.a = n;
                 self.set_zero(self.a == 0);
@@ -1487,33 +1574,33 @@ impl Cpu {
                 6
             }
             0xa4 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                self.y = self.mem.borrow_mut().read_u8(adr as u16);
+                self.y = self.read(adr as u16);
                 self.set_zero(self.y == 0);
                 self.set_negative(self.y >= 128);
                 3
             }
             0xa5 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                self.a = self.mem.borrow_mut().read_u8(adr as u16);
+                self.a = self.read(adr as u16);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 3
             }
             0xa6 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                self.x = self.mem.borrow_mut().read_u8(adr as u16);
+                self.x = self.read(adr as u16);
                 self.set_zero(self.x == 0);
                 self.set_negative(self.x >= 128);
                 3
             }
             0xa7 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.x = n;
                 self.a = n;
                 self.set_zero(self.a == 0);
@@ -1527,7 +1614,7 @@ impl Cpu {
                 2
             }
             0xa9 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.a = n;
                 self.set_zero(self.a == 0);
@@ -1542,33 +1629,33 @@ impl Cpu {
             }
 //            0xab => { 171 }
             0xac => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                self.y = self.mem.borrow_mut().read_u8(adr);
+                self.y = self.read(adr);
                 self.set_zero(self.y == 0);
                 self.set_negative(self.y >= 128);
                 4
             }
             0xad => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                self.a = self.mem.borrow_mut().read_u8(adr);
+                self.a = self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0xae => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                self.x = self.mem.borrow_mut().read_u8(adr);
+                self.x = self.read(adr);
                 self.set_zero(self.x == 0);
                 self.set_negative(self.x >= 128);
                 4
             }
             0xaf => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.x = n;
                 self.a = n;
                 self.set_zero(self.a == 0);
@@ -1581,7 +1668,7 @@ impl Cpu {
             }
             0xb1 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                self.a = self.mem.borrow_mut().read_u8(adr);
+                self.a = self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 5 + additional_cycles
@@ -1589,7 +1676,7 @@ impl Cpu {
 //            0xb2 => { 178 }
             0xb3 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.x = n;
                 self.a = n;
                 self.set_zero(self.a == 0);
@@ -1597,33 +1684,33 @@ impl Cpu {
                 5 + additional_cycles
             }
             0xb4 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                self.y = self.mem.borrow_mut().read_u8(adr);
+                self.y = self.read(adr);
                 self.set_zero(self.y == 0);
                 self.set_negative(self.y >= 128);
                 4
             }
             0xb5 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                self.a = self.mem.borrow_mut().read_u8(adr);
+                self.a = self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4
             }
             0xb6 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.y) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.y) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                self.x = self.mem.borrow_mut().read_u8(adr);
+                self.x = self.read(adr);
                 self.set_zero(self.x == 0);
                 self.set_negative(self.x >= 128);
                 4
             }
             0xb7 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.y);
+                let adr = self.read(self.pc).wrapping_add(self.y);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.x = n;
                 self.a = n;
                 self.set_zero(self.a == 0);
@@ -1636,7 +1723,7 @@ impl Cpu {
             }
             0xb9 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                self.a = self.mem.borrow_mut().read_u8(adr);
+                self.a = self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4 + additional_cycles
@@ -1650,28 +1737,28 @@ impl Cpu {
 //            0xbb => { 187 }
             0xbc => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                self.y = self.mem.borrow_mut().read_u8(adr);
+                self.y = self.read(adr);
                 self.set_zero(self.y == 0);
                 self.set_negative(self.y >= 128);
                 4 + additional_cycles
             }
             0xbd => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                self.a = self.mem.borrow_mut().read_u8(adr);
+                self.a = self.read(adr);
                 self.set_zero(self.a == 0);
                 self.set_negative(self.a >= 128);
                 4 + additional_cycles
             }
             0xbe => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                self.x = self.mem.borrow_mut().read_u8(adr);
+                self.x = self.read(adr);
                 self.set_zero(self.x == 0);
                 self.set_negative(self.x >= 128);
                 4 + additional_cycles
             }
             0xbf => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.x = n;
                 self.a = n;
                 self.set_zero(self.a == 0);
@@ -1679,7 +1766,7 @@ impl Cpu {
                 4 + additional_cycles
             }
             0xc0 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.set_negative(self.y.wrapping_sub(n) >= 128);
                 self.set_zero(self.y == n);
@@ -1688,7 +1775,7 @@ impl Cpu {
             }
             0xc1 => {
                 let adr = self.get_indirect_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
                 self.set_carry(self.a >= n);
@@ -1700,47 +1787,47 @@ impl Cpu {
             }
             0xc3 => {
                 let adr = self.get_indirect_x_addr();
-                let n_orig = self.mem.borrow_mut().read_u8(adr);
+                let n_orig = self.read(adr);
                 let n_sub = n_orig.wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr, n_sub);
+                self.write(adr, n_sub);
                 self.set_negative(self.a.wrapping_sub(n_sub) >= 128);
                 self.set_zero(self.a == n_sub);
                 self.set_carry(self.a >= n_sub);
                 8
             }
             0xc4 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.set_negative(self.y.wrapping_sub(n) >= 128);
                 self.set_zero(self.y == n);
                 self.set_carry(self.y >= n);
                 3
             }
             0xc5 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
                 self.set_carry(self.a >= n);
                 3
             }
             0xc6 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16).wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr as u16).wrapping_sub(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 5
             }
             0xc7 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc) as u16;
+                let adr = self.read(self.pc) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n_orig = self.mem.borrow_mut().read_u8(adr);
+                let n_orig = self.read(adr);
                 let n_sub = n_orig.wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr, n_sub);
+                self.write(adr, n_sub);
                 self.set_negative(self.a.wrapping_sub(n_sub) >= 128);
                 self.set_zero(self.a == n_sub);
                 self.set_carry(self.a >= n_sub);
@@ -1753,7 +1840,7 @@ impl Cpu {
                 2
             }
             0xc9 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
@@ -1768,38 +1855,38 @@ impl Cpu {
             }
 //            0xcb => { 203 }
             0xcc => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.set_negative(self.y.wrapping_sub(n) >= 128);
                 self.set_zero(self.y == n);
                 self.set_carry(self.y >= n);
                 4
             }
             0xcd => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
                 self.set_carry(self.a >= n);
                 4
             }
             0xce => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr).wrapping_sub(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 6
             }
             0xcf => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n_orig = self.mem.borrow_mut().read_u8(adr);
+                let n_orig = self.read(adr);
                 let n_sub = n_orig.wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr, n_sub);
+                self.write(adr, n_sub);
                 self.set_negative(self.a.wrapping_sub(n_sub) >= 128);
                 self.set_zero(self.a == n_sub);
                 self.set_carry(self.a >= n_sub);
@@ -1811,7 +1898,7 @@ impl Cpu {
             }
             0xd1 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
                 self.set_carry(self.a >= n);
@@ -1820,9 +1907,9 @@ impl Cpu {
 //            0xd2 => { 210 }
             0xd3 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n_orig = self.mem.borrow_mut().read_u8(adr);
+                let n_orig = self.read(adr);
                 let n_sub = n_orig.wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr, n_sub);
+                self.write(adr, n_sub);
                 self.set_negative(self.a.wrapping_sub(n_sub) >= 128);
                 self.set_zero(self.a == n_sub);
                 self.set_carry(self.a >= n_sub);
@@ -1833,29 +1920,29 @@ impl Cpu {
                 4
             }
             0xd5 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
                 self.set_carry(self.a >= n);
                 4
             }
             0xd6 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr).wrapping_sub(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 6
             }
             0xd7 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n_orig = self.mem.borrow_mut().read_u8(adr);
+                let n_orig = self.read(adr);
                 let n_sub = n_orig.wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr, n_sub);
+                self.write(adr, n_sub);
                 self.set_negative(self.a.wrapping_sub(n_sub) >= 128);
                 self.set_zero(self.a == n_sub);
                 self.set_carry(self.a >= n_sub);
@@ -1867,7 +1954,7 @@ impl Cpu {
             }
             0xd9 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
                 self.set_carry(self.a >= n);
@@ -1878,9 +1965,9 @@ impl Cpu {
             }
             0xdb => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n_orig = self.mem.borrow_mut().read_u8(adr);
+                let n_orig = self.read(adr);
                 let n_sub = n_orig.wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr, n_sub);
+                self.write(adr, n_sub);
                 self.set_negative(self.a.wrapping_sub(n_sub) >= 128);
                 self.set_zero(self.a == n_sub);
                 self.set_carry(self.a >= n_sub);
@@ -1892,7 +1979,7 @@ impl Cpu {
             }
             0xdd => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.set_negative(self.a.wrapping_sub(n) >= 128);
                 self.set_zero(self.a == n);
                 self.set_carry(self.a >= n);
@@ -1900,24 +1987,24 @@ impl Cpu {
             }
             0xde => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr).wrapping_sub(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 7
             }
             0xdf => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n_orig = self.mem.borrow_mut().read_u8(adr);
+                let n_orig = self.read(adr);
                 let n_sub = n_orig.wrapping_sub(1);
-                self.mem.borrow_mut().write_u8(adr, n_sub);
+                self.write(adr, n_sub);
                 self.set_negative(self.a.wrapping_sub(n_sub) >= 128);
                 self.set_zero(self.a == n_sub);
                 self.set_carry(self.a >= n_sub);
                 7
             }
             0xe0 => {
-                let n = self.mem.borrow_mut().read_u8(self.pc);
+                let n = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.set_negative(self.x.wrapping_sub(n) >= 128);
                 self.set_zero(self.x == n);
@@ -1926,7 +2013,7 @@ impl Cpu {
             }
             0xe1 => {
                 let adr = self.get_indirect_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.sbc(n);
                 6
             }
@@ -1936,41 +2023,41 @@ impl Cpu {
             }
             0xe3 => {
                 let adr = self.get_indirect_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr, n);
                 self.sbc(n);
                 8
             }
             0xe4 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.set_negative(self.x.wrapping_sub(n) >= 128);
                 self.set_zero(self.x == n);
                 self.set_carry(self.x >= n);
                 3
             }
             0xe5 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.sbc(n);
                 3
             }
             0xe6 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc);
+                let adr = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr as u16).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr as u16).wrapping_add(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 5
             }
             0xe7 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc) as u16;
+                let adr = self.read(self.pc) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr, n);
                 self.sbc(n);
                 5
             }
@@ -1981,7 +2068,7 @@ impl Cpu {
                 2
             }
             0xe9 => {
-                let n: u8 = self.mem.borrow_mut().read_u8(self.pc);
+                let n: u8 = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.sbc(n);
                 2
@@ -1990,41 +2077,41 @@ impl Cpu {
                 2
             }
             0xeb => {
-                let n: u8 = self.mem.borrow_mut().read_u8(self.pc);
+                let n: u8 = self.read(self.pc);
                 self.pc = self.pc.wrapping_add(1);
                 self.sbc(n);
                 2
             }
             0xec => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.set_negative(self.x.wrapping_sub(n) >= 128);
                 self.set_zero(self.x == n);
                 self.set_carry(self.x >= n);
                 4
             }
             0xed => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr as u16);
+                let n = self.read(adr as u16);
                 self.sbc(n);
                 4
             }
             0xee => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 6
             }
             0xef => {
-                let adr = self.mem.borrow_mut().read_u16(self.pc);
+                let adr = self.read_u16(self.pc);
                 self.pc = self.pc.wrapping_add(2);
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr, n);
                 self.sbc(n);
                 6
             }
@@ -2034,15 +2121,15 @@ impl Cpu {
             }
             0xf1 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.sbc(n);
                 5 + additional_cycles
             }
 //            0xf2 => { 242 }
             0xf3 => {
                 let (adr, additional_cycles) = self.get_indirect_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr, n);
                 self.sbc(n);
                 8
             }
@@ -2051,26 +2138,26 @@ impl Cpu {
                 4
             }
             0xf5 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.sbc(n);
                 4
             }
             0xf6 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 6
             }
             0xf7 => {
-                let adr = self.mem.borrow_mut().read_u8(self.pc).wrapping_add(self.x) as u16;
+                let adr = self.read(self.pc).wrapping_add(self.x) as u16;
                 self.pc = self.pc.wrapping_add(1);
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr, n);
                 self.sbc(n);
                 6
             }
@@ -2080,7 +2167,7 @@ impl Cpu {
             }
             0xf9 => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.sbc(n);
                 4 + additional_cycles
             }
@@ -2089,8 +2176,8 @@ impl Cpu {
             }
             0xfb => {
                 let (adr, additional_cycles) = self.get_absolute_y_addr();
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr, n);
                 self.sbc(n);
                 7
             }
@@ -2100,22 +2187,22 @@ impl Cpu {
             }
             0xfd => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr);
+                let n = self.read(adr);
                 self.sbc(n);
                 4 + additional_cycles
             }
             0xfe => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr as u16, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr as u16, n);
                 self.set_zero(n == 0);
                 self.set_negative(n >= 128);
                 7
             }
             0xff => {
                 let (adr, additional_cycles) = self.get_absolute_x_addr();
-                let n = self.mem.borrow_mut().read_u8(adr).wrapping_add(1);
-                self.mem.borrow_mut().write_u8(adr, n);
+                let n = self.read(adr).wrapping_add(1);
+                self.write(adr, n);
                 self.sbc(n);
                 7
             }
@@ -2124,9 +2211,173 @@ impl Cpu {
     }
 }
 
-impl fmt::Debug for Cpu {
+impl<B: Bus> fmt::Debug for Cpu<B> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Cpu {{ pc: 0x{:X}, a: 0x{:X}, x: 0x{:X}, y: 0x{:X}, s: 0x{:X}, p: 0x{:X} }}",
                self.pc, self.a, self.x, self.y, self.s, self.p)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    //Klaus Dormann's 6502_functional_test.bin assembled with the default settings:
+    // loaded at $0000, code starting at $0400 and decimal mode tests enabled.
+    // It's GPL licensed so it isn't in the repo, fetch it before running the ignored tests:
+    //   curl -Lo roms/6502_functional_test.bin \
+    //     https://github.com/Klaus2m5/6502_65C02_functional_tests/raw/master/bin_files/6502_functional_test.bin
+    //   cargo test -- --ignored
+    const FUNCTIONAL_TEST_PATH: &str = "./roms/6502_functional_test.bin";
+    const FUNCTIONAL_TEST_START: u16 = 0x0400;
+    const FUNCTIONAL_TEST_SUCCESS: u16 = 0x3469;
+    const FUNCTIONAL_TEST_CASE_ADR: u16 = 0x0200;
+    const FUNCTIONAL_TEST_MAX_CYCLES: u64 = 200_000_000;
+
+    struct FlatRam {
+        ram: Vec<u8>,
+    }
+
+    impl Bus for FlatRam {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+        fn write(&mut self, addr: u16, val: u8) {
+            self.ram[addr as usize] = val;
+        }
+        fn peek(&self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn klaus_dormann_functional_test() {
+        let image = fs::read(FUNCTIONAL_TEST_PATH)
+            .unwrap_or_else(|e| panic!("Can't read {} ({}), see FUNCTIONAL_TEST_PATH for where to get it", FUNCTIONAL_TEST_PATH, e));
+        let mut ram = vec![0; 0x10000];
+        ram[..image.len()].copy_from_slice(&image);
+
+        let mut cpu = Cpu::new(FlatRam { ram });
+        cpu.variant = CpuVariant::Nmos6502;
+        cpu.pc = FUNCTIONAL_TEST_START;
+
+        //Every failure (and the final success) ends in a jump or branch to itself
+        loop {
+            let pc_before = cpu.pc;
+            cpu.emulate();
+            if cpu.pc == pc_before {
+                break;
+            }
+            assert!(cpu.cycles < FUNCTIONAL_TEST_MAX_CYCLES,
+                    "Functional test did not finish, last pc: 0x{:04X}", cpu.pc);
+        }
+        let test_case = cpu.bus.read(FUNCTIONAL_TEST_CASE_ADR);
+        assert_eq!(cpu.pc, FUNCTIONAL_TEST_SUCCESS,
+                   "Trapped at 0x{:04X} in test case 0x{:02X}", cpu.pc, test_case);
+    }
+
+    //Logs every bus access and tick, to check what order an instruction does them in
+    struct LoggingBus {
+        ram: Vec<u8>,
+        log: Vec<(char, u16)>,
+    }
+
+    impl Bus for LoggingBus {
+        fn read(&mut self, addr: u16) -> u8 {
+            self.log.push(('r', addr));
+            self.ram[addr as usize]
+        }
+        fn write(&mut self, addr: u16, val: u8) {
+            self.log.push(('w', addr));
+            self.ram[addr as usize] = val;
+        }
+        fn peek(&self, addr: u16) -> u8 {
+            self.ram[addr as usize]
+        }
+        fn tick(&mut self, cycles: u8) {
+            for _ in 0..cycles {
+                self.log.push(('t', 0));
+            }
+        }
+    }
+
+    const SED: u8 = 0xF8;
+    const CLC: u8 = 0x18;
+    const SEC: u8 = 0x38;
+    const LDA_IMM: u8 = 0xA9;
+    const ADC_IMM: u8 = 0x69;
+    const SBC_IMM: u8 = 0xE9;
+
+    fn run_program(variant: CpuVariant, program: &[u8]) -> Cpu<FlatRam> {
+        let mut ram = vec![0; 0x10000];
+        ram[0x0400..0x0400 + program.len()].copy_from_slice(program);
+        let mut cpu = Cpu::new(FlatRam { ram });
+        cpu.variant = variant;
+        cpu.pc = 0x0400;
+        while (cpu.pc as usize) < 0x0400 + program.len() {
+            cpu.emulate();
+        }
+        cpu
+    }
+
+    #[test]
+    fn decimal_adc_carries_out_with_zero_from_the_binary_sum() {
+        let mut cpu = run_program(CpuVariant::Nmos6502, &[SED, CLC, LDA_IMM, 0x99, ADC_IMM, 0x01]);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_carry());
+        //The binary sum is $9A, so Z stays clear even though A ends up 0
+        assert!(!cpu.get_zero());
+    }
+
+    #[test]
+    fn decimal_sbc_borrows() {
+        let mut cpu = run_program(CpuVariant::Nmos6502, &[SED, SEC, LDA_IMM, 0x00, SBC_IMM, 0x01]);
+        assert_eq!(cpu.a, 0x99);
+        assert!(!cpu.get_carry());
+        //Flags come from the binary $FF
+        assert!(cpu.get_negative());
+        assert!(!cpu.get_zero());
+        assert!(!cpu.get_overflow());
+    }
+
+    #[test]
+    fn decimal_adc_takes_n_and_v_from_the_half_adjusted_sum() {
+        //79 + 00 + 1 = 80: the low nibble carry makes $80, a signed overflow
+        let mut cpu = run_program(CpuVariant::Nmos6502, &[SED, SEC, LDA_IMM, 0x79, ADC_IMM, 0x00]);
+        assert_eq!(cpu.a, 0x80);
+        assert!(cpu.get_negative());
+        assert!(cpu.get_overflow());
+        assert!(!cpu.get_carry());
+        //99 + 01: N comes from $A0, before the high nibble gets adjusted
+        let mut cpu = run_program(CpuVariant::Nmos6502, &[SED, CLC, LDA_IMM, 0x99, ADC_IMM, 0x01]);
+        assert!(cpu.get_negative());
+        assert!(!cpu.get_overflow());
+    }
+
+    #[test]
+    fn ricoh_2a03_ignores_decimal_flag() {
+        let mut cpu = run_program(CpuVariant::Ricoh2A03, &[SED, CLC, LDA_IMM, 0x09, ADC_IMM, 0x01]);
+        assert_eq!(cpu.a, 0x0A);
+        assert!(cpu.get_decimal());
+        let cpu = run_program(CpuVariant::Ricoh2A03, &[SED, SEC, LDA_IMM, 0x00, SBC_IMM, 0x01]);
+        assert_eq!(cpu.a, 0xFF);
+    }
+
+    #[test]
+    fn bus_is_ticked_after_each_access() {
+        //The PPU's vblank races rely on a register read landing before that cycle's PPU dots
+        let mut ram = vec![0; 0x10000];
+        ram[0x0400..0x0406].copy_from_slice(&[0xAD, 0x02, 0x20, 0x8D, 0x00, 0x20]); //LDA $2002, STA $2000
+        let mut cpu = Cpu::new(LoggingBus { ram, log: vec![] });
+        cpu.pc = 0x0400;
+        //Leave out the reset vector fetch
+        cpu.bus.log.clear();
+        cpu.emulate();
+        cpu.emulate();
+        assert_eq!(cpu.bus.log, vec![
+            ('r', 0x0400), ('t', 0), ('r', 0x0401), ('t', 0), ('r', 0x0402), ('t', 0), ('r', 0x2002), ('t', 0),
+            ('r', 0x0403), ('t', 0), ('r', 0x0404), ('t', 0), ('r', 0x0405), ('t', 0), ('w', 0x2000), ('t', 0),
+        ]);
+    }
+}
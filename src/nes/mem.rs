@@ -1,107 +1,32 @@
+use crate::nes::bus::Bus;
+use crate::nes::cartridge::Cartridge;
+use crate::nes::ppu::Ppu;
+
 pub struct Mem {
     ram: [u8; 0x800],
-    vram: [u8; 0x4000],
-    pub oam: [u8; 256],
-    pgr_rom: Vec<u8>,
-    chr_rom: Vec<u8>,
-    pub log_string: String,
+    pub ppu: Ppu,
+    pub cartridge: Cartridge,
     pub irq: u8,
-    ppu_target_adr: u16,
-    val_to_write_to_vram: u8,
-    ppu_writing_high_adress_bit: bool,
-    ppu_stat: u8,
-    nmi_occured: bool,
-    nmi_output: bool,
-    ppu_ctrl: u8,
-    trigger_nmi: bool,
-    scroll_latch: bool,
-    scroll_x: u8,
-    scroll_y: u8,
-    oam_adr: u8,
-    ppu_mask: u8,
     key_presses: u8,
-    keys_snapshot: u8
+    keys_snapshot: u8,
+    open_bus: u8,
+    //Leftover fifths of a dot on PAL, where the PPU runs 3.2 dots per CPU cycle
+    ppu_dot_remainder: u32,
 }
 
 impl Mem {
-    pub fn new(pgr_rom: Vec<u8>, chr_rom: Vec<u8>) -> Mem {
+    pub fn new(cartridge: Cartridge, ppu: Ppu) -> Mem {
         Mem {
             ram: [0; 0x800],
-            vram: [0; 0x4000],
-            oam: [0; 256],
-            pgr_rom,
-            chr_rom,
-            log_string: "".to_string(),
+            ppu,
+            cartridge,
             irq: 1,
-            ppu_target_adr: 0,
-            ppu_writing_high_adress_bit: true,
-            val_to_write_to_vram: 0,
-            ppu_stat: 0,
-            nmi_occured: false,
-            nmi_output: false,
-            ppu_ctrl: 0,
-            trigger_nmi: true,
-            scroll_latch: false,
-            scroll_x: 0,
-            scroll_y: 0,
-            oam_adr: 0,
-            ppu_mask: 0,
             key_presses: 0,
-            keys_snapshot: 0
-        }
-    }
-    pub fn should_increment_by_1(&mut self) -> bool {
-        return (self.ppu_ctrl & 0b100) == 0
-    }
-    pub fn use_chr_0(&mut self) -> bool {
-        return (self.ppu_ctrl & 0b10000) == 0
-    }
-    pub fn get_nmi_enable(&mut self) -> bool {
-        (self.ppu_ctrl & 128) > 0
-    }
-    pub fn set_nmi_output(&mut self, set: bool) {
-        self.nmi_output = set;
-    }
-    pub fn get_nmi_output(&mut self) -> bool {
-        self.nmi_output
-    }
-    pub fn set_nmi_occured(&mut self, set: bool) {
-        self.nmi_occured = set;
-    }
-    pub fn get_nmi_occured(&mut self) -> bool {
-        self.nmi_occured
-    }
-    pub fn set_trigger_nmi(&mut self, set: bool) {
-        self.trigger_nmi = set;
-    }
-    pub fn get_trigger_nmi(&mut self) -> bool {
-        self.trigger_nmi
-    }
-    pub fn get_scroll_x(&mut self) -> u8 {
-        self.scroll_x
-    }
-    pub fn get_scroll_y(&mut self) -> u8 {
-        self.scroll_y
-    }
-    pub fn get_nametable_index(&mut self) -> u8 {
-        self.ppu_ctrl & 0b11
-    }
-    pub fn get_oam_chr_number(&mut self) -> u8 {
-        (self.ppu_ctrl & 0b1000) >> 3
-    }
-    pub fn set_sprite_0_hit(&mut self, hit: bool) {
-        if hit {
-            self.ppu_stat |= 0b01000000;
-        } else {
-            self.ppu_stat &= 0b10111111;
+            keys_snapshot: 0,
+            open_bus: 0,
+            ppu_dot_remainder: 0,
         }
     }
-    pub fn should_use_big_sprites(&mut self) -> bool {
-        self.ppu_stat & 0b00100000 > 0
-    }
-    pub fn draw_sprites(&mut self) -> bool {
-        self.ppu_mask & 0b00010000 > 0
-    }
     pub fn button_set(&mut self, bit_index: u8, set: bool) {
         if set {
             self.key_presses |= (1 << bit_index);
@@ -110,132 +35,71 @@ impl Mem {
         }
     }
     pub fn read_u8(&mut self, addr: u16) -> u8 {
+        let data = self.read_u8_from_bus(addr);
+        self.open_bus = data;
+        data
+    }
+
+    fn read_u8_from_bus(&mut self, addr: u16) -> u8 {
         match addr {
-            0x0..=0x17FF => {
+            0x0..=0x1FFF => {
                 self.ram[addr as usize % self.ram.len()]
             }
             0x2000..=0x3FFF => {
-                let ppu_reg = addr % 8;
-//                println!("Reading ppu reg: {:?}", ppu_reg);
-                match ppu_reg {
-                    0 => {
-                        self.ppu_ctrl
-                    }
-                    1 => {
-                        self.ppu_mask
-                    }
-                    2 => {
-                        let mut data = self.ppu_stat;
-                        if self.nmi_occured {
-                            data = data | 128;
-                        } else {
-                            data = data & 0b01111111;
-                        }
-                        self.set_nmi_occured(false);
-                        self.ppu_writing_high_adress_bit = true;
-                        self.scroll_latch = false;
-                        return data;
-                    }
-                    7 => {
-                        let ret_val = self.read_vram(self.ppu_target_adr);
-                        if self.should_increment_by_1() {
-                            self.ppu_target_adr = self.ppu_target_adr.wrapping_add(1);
-                        } else {
-                            self.ppu_target_adr = self.ppu_target_adr.wrapping_add(32);
-                        }
-                        if self.ppu_target_adr == 0x4000 {
-                            self.ppu_target_adr = 0;
-                        }
-                        ret_val
-                    }
-                    _ => 0
-                }
+                self.ppu.read_register(addr % 8, &mut self.cartridge)
             }
             0x4016..=0x4017 => {
                 let data = (self.keys_snapshot >> 7) & 0b1;
                 self.keys_snapshot = self.keys_snapshot << 1;
-                data
+                //The controller only drives the low bits, the upper ones are open bus
+                (self.open_bus & 0b11100000) | data
+            }
+            0x6000..=0x7FFF => {
+                self.cartridge.read_prg_ram(addr)
             }
             0x8000..=0xFFFF => {
-                let mut real_addr = addr - 0x8000;
-//                if self.pgr_rom.len() <= 0x4000 {
-                real_addr = real_addr % 0x4000;
-//                }
-                self.pgr_rom[real_addr as usize]
+                self.cartridge.read_prg(addr)
             }
-            _ => { 0 }
+            _ => { self.open_bus }
         }
     }
 
-    pub fn read_signed(&mut self, addr: u16) -> i8 {
-        self.read_u8(addr) as i8
-    }
-
-    pub fn read_u16(&mut self, addr: u16) -> u16 {
-        let lower_byte = self.read_u8(addr);
-        let upper_byte = self.read_u8(addr.wrapping_add(1));
-
-        (lower_byte as u16) | ((upper_byte as u16) << 8)
+    pub fn peek_u8(&self, addr: u16) -> u8 {
+        match addr {
+            0x0..=0x1FFF => {
+                self.ram[addr as usize % self.ram.len()]
+            }
+            0x2000..=0x3FFF => {
+                self.ppu.peek_register(addr % 8, &self.cartridge)
+            }
+            0x4016..=0x4017 => {
+                (self.open_bus & 0b11100000) | ((self.keys_snapshot >> 7) & 0b1)
+            }
+            0x6000..=0x7FFF => {
+                self.cartridge.peek_prg_ram(addr)
+            }
+            0x8000..=0xFFFF => {
+                self.cartridge.peek_prg(addr)
+            }
+            _ => { self.open_bus }
+        }
     }
 
     pub fn write_u8(&mut self, addr: u16, val: u8) {
+        self.open_bus = val;
         match addr {
-            0x0..=0x17FF => {
+            0x0..=0x1FFF => {
                 self.ram[addr as usize % self.ram.len()] = val;
             }
             0x2000..=0x3FFF => {
-                let ppu_reg = addr % 8;
-//                println!("Writing: 0x{:X} to ppu register {:?}", val, ppu_reg);
-                match ppu_reg {
-                    0 => {
-                        self.ppu_ctrl = val;
-                        self.set_nmi_output(self.ppu_ctrl >= 128);
-                    }
-                    1 => {
-                        self.ppu_mask = val;
-                    }
-                    3 => {
-                        self.oam_adr = val;
-                    }
-                    4 => {
-                        //TODO: OAMDATA
-                    }
-                    5 => {
-                        if !self.scroll_latch {
-                            self.scroll_x = val;
-                        } else {
-                            self.scroll_y = val;
-                        }
-                        self.scroll_latch = !self.scroll_latch;
-                    }
-                    6 => {
-                        if self.ppu_writing_high_adress_bit {
-                            self.ppu_target_adr = (self.ppu_target_adr & 0xFF) | ((val as u16) << 8);
-                            self.ppu_writing_high_adress_bit = false;
-                        } else {
-                            self.ppu_target_adr = (self.ppu_target_adr & 0xFF00) | (val as u16);
-                            self.ppu_writing_high_adress_bit = true;
-                        }
-                    }
-                    7 => {
-                        self.val_to_write_to_vram = val;
-                        self.write_vram(self.ppu_target_adr, self.val_to_write_to_vram);
-                        if self.should_increment_by_1() {
-                            self.ppu_target_adr = self.ppu_target_adr.wrapping_add(1);
-                        } else {
-                            self.ppu_target_adr = self.ppu_target_adr.wrapping_add(32);
-                        }
-                        if self.ppu_target_adr == 0x4000 {
-                            self.ppu_target_adr = 0;
-                        }
-                    }
-                    _ => ()
-                }
+                self.ppu.write_register(addr % 8, val, &mut self.cartridge);
             }
             0x4014 => {
-                let mut adr = (val as u16) << 8;
+                let adr = (val as u16) << 8;
+                //DMA goes through OAMDATA, so it starts at the current OAMADDR
                 for i in 0..256 {
-                    self.oam[i as usize] = self.read_u8(adr + i);
+                    let data = self.read_u8(adr + i);
+                    self.write_u8(0x2004, data);
                 }
             }
             0x4016..=0x4017 => {
@@ -243,52 +107,40 @@ impl Mem {
                     self.keys_snapshot = self.key_presses;
                 }
             }
+            0x6000..=0x7FFF => {
+                self.cartridge.write_prg_ram(addr, val);
+            }
             _ => {}
         }
     }
+}
 
-    pub fn read_vram(&mut self, addr: u16) -> u8 {
-        match addr {
-            0..=0x1FFF => {
-                self.chr_rom[addr as usize]
-            }
-            0x2000..=0x2FFF => {
-                self.vram[addr as usize]
-            }
-            0x3000..=0x3EFF => {
-                self.vram[(addr - 0x1000) as usize]
-            }
-            0x3F00..=0x3F1F => {
-                self.vram[addr as usize]
-            }
-            0x3F20..=0x3FFF => {
-                self.vram[(addr - 0x20) as usize]
-            }
-            _ => self.vram[addr as usize]
-        }
+impl Bus for Mem {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.read_u8(addr)
     }
-
-    pub fn write_vram(&mut self, addr: u16, val: u8) {
-        match addr {
-            0..=0x1FFF => {
-                panic!("Yet to be implemented (write {:X} to {:X})", val, addr);
-            }
-            0x2000..=0x2FFF => {
-//                println!("VRAM: writing {:X} to {:X}", val, addr);
-                self.vram[addr as usize] = val;
-            }
-            0x3000..=0x3EFF => {
-                self.vram[(addr - 0x1000) as usize] = val;
-            }
-            0x3F00..=0x3F1F => {
-                self.vram[addr as usize] = val;
-            }
-            0x3F20..=0x3FFF => {
-                self.vram[(addr - 0x20) as usize] = val;
-            }
-            _ => {
-                panic!("Out of range (write {:X} to {:X})", val, addr);
-            }
-        }
+    fn write(&mut self, addr: u16, val: u8) {
+        self.write_u8(addr, val)
     }
-}
\ No newline at end of file
+    fn peek(&self, addr: u16) -> u8 {
+        self.peek_u8(addr)
+    }
+    fn tick(&mut self, cycles: u8) {
+        let (dots, per_cycles) = self.ppu.get_region().ppu_dots_per_cpu_cycle();
+        let total = cycles as u32 * dots + self.ppu_dot_remainder;
+        self.ppu_dot_remainder = total % per_cycles;
+        self.ppu.emulate((total / per_cycles) as u8, &mut self.cartridge);
+    }
+    fn irq_pending(&mut self) -> bool {
+        self.irq == 0
+    }
+    fn acknowledge_irq(&mut self) {
+        self.irq = 1;
+    }
+    fn nmi_pending(&mut self) -> bool {
+        self.ppu.get_trigger_nmi()
+    }
+    fn acknowledge_nmi(&mut self) {
+        self.ppu.set_trigger_nmi(false);
+    }
+}
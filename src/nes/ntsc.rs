@@ -0,0 +1,102 @@
+use crate::nes::palette::{NtscSettings, SIGNAL_PHASES, composite_signal_level, subcarrier_angle, yiq_to_rgb};
+use crate::nes::ppu::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+//A pixel (dot) lasts 4 master clocks and the subcarrier cycle 6, which the model splits into 12 phases,
+// so every pixel is 8 samples: 2 per master clock
+const SAMPLES_PER_PIXEL: usize = 8;
+const SAMPLES_PER_LINE: usize = SCREEN_WIDTH * SAMPLES_PER_PIXEL;
+//A full scanline (341 dots) is 2728 samples, which shifts the subcarrier by 4 phases every line
+const LINE_PHASE_SHIFT: usize = 341 * SAMPLES_PER_PIXEL % SIGNAL_PHASES;
+//Luma is averaged over one subcarrier cycle, which removes the chroma from it.
+// Chroma is averaged over two, which is what blurs colours across pixel edges
+const LUMA_WIDTH: usize = SIGNAL_PHASES;
+const CHROMA_WIDTH: usize = SIGNAL_PHASES * 2;
+//256 pixels at the NTSC 8:7 pixel aspect ratio, with some room for the blur, like nes_ntsc
+pub const NTSC_WIDTH: usize = 602;
+pub const NTSC_HEIGHT: usize = SCREEN_HEIGHT;
+
+pub struct NtscFilter {
+    pub settings: NtscSettings,
+    //When on, each frame is decoded at the subcarrier phase the PPU drew it with, so artifacts crawl like on a TV
+    pub dot_crawl: bool,
+    output: Vec<(u8, u8, u8)>,
+    //Per-line scratch buffers with running sums of the signal and its I/Q demodulation
+    luma_sums: Vec<f32>,
+    i_sums: Vec<f32>,
+    q_sums: Vec<f32>,
+}
+
+impl NtscFilter {
+    pub fn new(settings: NtscSettings) -> NtscFilter {
+        NtscFilter {
+            settings,
+            dot_crawl: true,
+            output: vec![(0, 0, 0); NTSC_WIDTH * NTSC_HEIGHT],
+            luma_sums: vec![0.0; SAMPLES_PER_LINE + 1],
+            i_sums: vec![0.0; SAMPLES_PER_LINE + 1],
+            q_sums: vec![0.0; SAMPLES_PER_LINE + 1],
+        }
+    }
+
+    pub fn apply(&mut self, frame: &[u16], frame_phase: usize) -> &[(u8, u8, u8)] {
+        //Turns a palette-index frame into the composite signal and decodes it back to RGB.
+        // The signal is generated from the hardware levels, so the selected palette doesn't apply here
+        let (cos_table, sin_table) = self.demodulation_tables();
+        let frame_phase = if self.dot_crawl { frame_phase } else { 0 };
+        for y in 0..SCREEN_HEIGHT {
+            let line_phase = (frame_phase + y * LINE_PHASE_SHIFT) % SIGNAL_PHASES;
+            self.encode_line(&frame[y * SCREEN_WIDTH..(y + 1) * SCREEN_WIDTH], line_phase, &cos_table, &sin_table);
+            self.decode_line(y);
+        }
+        &self.output
+    }
+
+    fn demodulation_tables(&self) -> ([f32; SIGNAL_PHASES], [f32; SIGNAL_PHASES]) {
+        let mut cos_table = [0.0; SIGNAL_PHASES];
+        let mut sin_table = [0.0; SIGNAL_PHASES];
+        for phase in 0..SIGNAL_PHASES {
+            let angle = subcarrier_angle(phase, self.settings.hue);
+            cos_table[phase] = angle.cos();
+            sin_table[phase] = angle.sin();
+        }
+        (cos_table, sin_table)
+    }
+
+    fn encode_line(&mut self, line: &[u16], line_phase: usize,
+                   cos_table: &[f32; SIGNAL_PHASES], sin_table: &[f32; SIGNAL_PHASES]) {
+        let (mut luma, mut i, mut q) = (0.0, 0.0, 0.0);
+        for sample in 0..SAMPLES_PER_LINE {
+            let phase = (line_phase + sample) % SIGNAL_PHASES;
+            let level = composite_signal_level(line[sample / SAMPLES_PER_PIXEL], phase);
+            luma += level;
+            i += level * cos_table[phase];
+            q += level * sin_table[phase];
+            self.luma_sums[sample + 1] = luma;
+            self.i_sums[sample + 1] = i;
+            self.q_sums[sample + 1] = q;
+        }
+    }
+
+    fn decode_line(&mut self, y: usize) {
+        let settings = self.settings;
+        for x in 0..NTSC_WIDTH {
+            let center = (x * SAMPLES_PER_LINE + SAMPLES_PER_LINE / 2) / NTSC_WIDTH;
+            let luma = window_average(&self.luma_sums, center, LUMA_WIDTH);
+            let i = window_average(&self.i_sums, center, CHROMA_WIDTH);
+            let q = window_average(&self.q_sums, center, CHROMA_WIDTH);
+            self.output[y * NTSC_WIDTH + x] = yiq_to_rgb(
+                luma * settings.contrast + settings.brightness,
+                i * settings.saturation,
+                q * settings.saturation,
+                settings.gamma,
+            );
+        }
+    }
+}
+
+fn window_average(sums: &[f32], center: usize, width: usize) -> f32 {
+    //Samples past the ends of the line are treated as blank, which fades the borders out
+    let start = center.saturating_sub(width / 2);
+    let end = (center + width / 2).min(sums.len() - 1);
+    (sums[end] - sums[start]) / width as f32
+}
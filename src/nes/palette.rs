@@ -1,69 +1,417 @@
-pub fn get_rgb_color(index: u8) -> (u8, u8, u8) {
-    match index {
-        0x00 => { (0x54, 0x54, 0x54) }
-        0x01 => { (0x00, 0x1E, 0x74) }
-        0x02 => { (0x08, 0x10, 0x90) }
-        0x03 => { (0x30, 0x00, 0x88) }
-        0x04 => { (0x44, 0x00, 0x64) }
-        0x05 => { (0x5C, 0x00, 0x30) }
-        0x06 => { (0x54, 0x04, 0x00) }
-        0x07 => { (0x3C, 0x18, 0x00) }
-        0x08 => { (0x20, 0x2A, 0x00) }
-        0x09 => { (0x08, 0x3A, 0x00) }
-        0x0A => { (0x00, 0x40, 0x00) }
-        0x0B => { (0x00, 0x3C, 0x00) }
-        0x0C => { (0x00, 0x32, 0x3C) }
-        0x0D => { (0x00, 0x00, 0x00) }
-        0x0E => { (0x00, 0x00, 0x00) }
-        0x0F => { (0x00, 0x00, 0x00) }
-        0x10 => { (0x98, 0x96, 0x98) }
-        0x11 => { (0x08, 0x4C, 0xC4) }
-        0x12 => { (0x30, 0x32, 0xEC) }
-        0x13 => { (0x5C, 0x1E, 0xE4) }
-        0x14 => { (0x88, 0x14, 0xB0) }
-        0x15 => { (0xA0, 0x14, 0x64) }
-        0x16 => { (0x98, 0x22, 0x20) }
-        0x17 => { (0x78, 0x3C, 0x00) }
-        0x18 => { (0x54, 0x5A, 0x00) }
-        0x19 => { (0x28, 0x72, 0x00) }
-        0x1A => { (0x08, 0x7C, 0x00) }
-        0x1B => { (0x00, 0x76, 0x28) }
-        0x1C => { (0x00, 0x66, 0x78) }
-        0x1D => { (0x00, 0x00, 0x00) }
-        0x1E => { (0x00, 0x00, 0x00) }
-        0x1F => { (0x00, 0x00, 0x00) }
-        0x20 => { (0xEC, 0xEE, 0xEC) }
-        0x21 => { (0x4C, 0x9A, 0xEC) }
-        0x22 => { (0x78, 0x7C, 0xEC) }
-        0x23 => { (0xB0, 0x62, 0xEC) }
-        0x24 => { (0xE4, 0x54, 0xEC) }
-        0x25 => { (0xEC, 0x58, 0xB4) }
-        0x26 => { (0xEC, 0x6A, 0x64) }
-        0x27 => { (0xD4, 0x88, 0x20) }
-        0x28 => { (0xA0, 0xAA, 0x00) }
-        0x29 => { (0x74, 0xC4, 0x00) }
-        0x2A => { (0x4C, 0xD0, 0x20) }
-        0x2B => { (0x38, 0xCC, 0x6C) }
-        0x2C => { (0x38, 0xB4, 0xCC) }
-        0x2D => { (0x3C, 0x3C, 0x3C) }
-        0x2E => { (0x00, 0x00, 0x00) }
-        0x2F => { (0x00, 0x00, 0x00) }
-        0x30 => { (0xEC, 0xEE, 0xEC) }
-        0x31 => { (0xA8, 0xCC, 0xEC) }
-        0x32 => { (0xBC, 0xBC, 0xEC) }
-        0x33 => { (0xD4, 0xB2, 0xEC) }
-        0x34 => { (0xEC, 0xAE, 0xEC) }
-        0x35 => { (0xEC, 0xAE, 0xD4) }
-        0x36 => { (0xEC, 0xB4, 0xB0) }
-        0x37 => { (0xE4, 0xC4, 0x90) }
-        0x38 => { (0xCC, 0xD2, 0x78) }
-        0x39 => { (0xB4, 0xDE, 0x78) }
-        0x3A => { (0xA8, 0xE2, 0x90) }
-        0x3B => { (0x98, 0xE2, 0xB4) }
-        0x3C => { (0xA0, 0xD6, 0xE4) }
-        0x3D => { (0xA0, 0xA2, 0xA0) }
-        0x3E => { (0x00, 0x00, 0x00) }
-        0x3F => { (0x00, 0x00, 0x00) }
-        _ => (index * (255 / 4), index * (255 / 4), index * (255 / 4))
-    }
-}
\ No newline at end of file
+use std::f32::consts::PI;
+use std::fs;
+
+//How much the channels that are not emphasized get darkened by the PPUMASK emphasis bits
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+//Lines the decoded hues up with the colour numbers (in phases, 30 degrees each)
+const NTSC_HUE_OFFSET: f32 = 3.9;
+//The 2C07 puts its colours half a phase (15 degrees) away from where the 2C02 has them.
+// A PAL TV averages each line with the next one, so it doesn't show phase errors and has no hue knob
+const PAL_HUE_OFFSET: f32 = NTSC_HUE_OFFSET - 0.5;
+//A .pal file is either just the 64 base colours, or all 8 emphasis combinations of them
+const PAL_FILE_SIZE: usize = 64 * 3;
+const PAL_FILE_WITH_EMPHASIS_SIZE: usize = 512 * 3;
+
+//2C02 composite output voltages for the 4 luma levels, relative to sync
+const SIGNAL_LOW: [f32; 4] = [0.350, 0.518, 0.962, 1.550];
+const SIGNAL_HIGH: [f32; 4] = [1.094, 1.506, 1.962, 1.962];
+const SIGNAL_BLACK: f32 = 0.518;
+const SIGNAL_WHITE: f32 = 1.962;
+//The colour subcarrier cycle is split into 12 phases, one colour (hue) per phase
+pub const SIGNAL_PHASES: usize = 12;
+
+const PALETTE_2C02: [(u8, u8, u8); 64] = [
+    (0x54, 0x54, 0x54), (0x00, 0x1E, 0x74), (0x08, 0x10, 0x90), (0x30, 0x00, 0x88),
+    (0x44, 0x00, 0x64), (0x5C, 0x00, 0x30), (0x54, 0x04, 0x00), (0x3C, 0x18, 0x00),
+    (0x20, 0x2A, 0x00), (0x08, 0x3A, 0x00), (0x00, 0x40, 0x00), (0x00, 0x3C, 0x00),
+    (0x00, 0x32, 0x3C), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0x98, 0x96, 0x98), (0x08, 0x4C, 0xC4), (0x30, 0x32, 0xEC), (0x5C, 0x1E, 0xE4),
+    (0x88, 0x14, 0xB0), (0xA0, 0x14, 0x64), (0x98, 0x22, 0x20), (0x78, 0x3C, 0x00),
+    (0x54, 0x5A, 0x00), (0x28, 0x72, 0x00), (0x08, 0x7C, 0x00), (0x00, 0x76, 0x28),
+    (0x00, 0x66, 0x78), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xEC, 0xEE, 0xEC), (0x4C, 0x9A, 0xEC), (0x78, 0x7C, 0xEC), (0xB0, 0x62, 0xEC),
+    (0xE4, 0x54, 0xEC), (0xEC, 0x58, 0xB4), (0xEC, 0x6A, 0x64), (0xD4, 0x88, 0x20),
+    (0xA0, 0xAA, 0x00), (0x74, 0xC4, 0x00), (0x4C, 0xD0, 0x20), (0x38, 0xCC, 0x6C),
+    (0x38, 0xB4, 0xCC), (0x3C, 0x3C, 0x3C), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xEC, 0xEE, 0xEC), (0xA8, 0xCC, 0xEC), (0xBC, 0xBC, 0xEC), (0xD4, 0xB2, 0xEC),
+    (0xEC, 0xAE, 0xEC), (0xEC, 0xAE, 0xD4), (0xEC, 0xB4, 0xB0), (0xE4, 0xC4, 0x90),
+    (0xCC, 0xD2, 0x78), (0xB4, 0xDE, 0x78), (0xA8, 0xE2, 0x90), (0x98, 0xE2, 0xB4),
+    (0xA0, 0xD6, 0xE4), (0xA0, 0xA2, 0xA0), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+const PALETTE_FCEUX: [(u8, u8, u8); 64] = [
+    (0x74, 0x74, 0x74), (0x24, 0x18, 0x8C), (0x00, 0x00, 0xA8), (0x44, 0x00, 0x9C),
+    (0x8C, 0x00, 0x74), (0xA8, 0x00, 0x10), (0xA4, 0x00, 0x00), (0x7C, 0x08, 0x00),
+    (0x40, 0x2C, 0x00), (0x00, 0x44, 0x00), (0x00, 0x50, 0x00), (0x00, 0x3C, 0x14),
+    (0x18, 0x3C, 0x5C), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xBC, 0xBC, 0xBC), (0x00, 0x70, 0xEC), (0x20, 0x38, 0xEC), (0x80, 0x00, 0xF0),
+    (0xBC, 0x00, 0xBC), (0xE4, 0x00, 0x58), (0xD8, 0x28, 0x00), (0xC8, 0x4C, 0x0C),
+    (0x88, 0x70, 0x00), (0x00, 0x94, 0x00), (0x00, 0xA8, 0x00), (0x00, 0x90, 0x38),
+    (0x00, 0x80, 0x88), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFC, 0xFC, 0xFC), (0x3C, 0xBC, 0xFC), (0x5C, 0x94, 0xFC), (0xCC, 0x88, 0xFC),
+    (0xF4, 0x78, 0xFC), (0xFC, 0x74, 0xB4), (0xFC, 0x74, 0x60), (0xFC, 0x98, 0x38),
+    (0xF0, 0xBC, 0x3C), (0x80, 0xD0, 0x10), (0x4C, 0xDC, 0x48), (0x58, 0xF8, 0x98),
+    (0x00, 0xE8, 0xD8), (0x78, 0x78, 0x78), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFC, 0xFC, 0xFC), (0xA8, 0xE4, 0xFC), (0xC4, 0xD4, 0xFC), (0xD4, 0xC8, 0xFC),
+    (0xFC, 0xC4, 0xFC), (0xFC, 0xC4, 0xD8), (0xFC, 0xBC, 0xB0), (0xFC, 0xD8, 0xA8),
+    (0xFC, 0xE4, 0xA0), (0xE0, 0xFC, 0xA0), (0xA8, 0xF0, 0xBC), (0xB0, 0xFC, 0xCC),
+    (0x9C, 0xFC, 0xF0), (0xC4, 0xC4, 0xC4), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+//Nestopia's default palette, from its YUV decoder
+const PALETTE_NESTOPIA: [(u8, u8, u8); 64] = [
+    (0x66, 0x66, 0x66), (0x00, 0x2A, 0x88), (0x14, 0x12, 0xA7), (0x3B, 0x00, 0xA4),
+    (0x5C, 0x00, 0x7E), (0x6E, 0x00, 0x40), (0x6C, 0x06, 0x00), (0x56, 0x1D, 0x00),
+    (0x33, 0x35, 0x00), (0x0B, 0x48, 0x00), (0x00, 0x52, 0x00), (0x00, 0x4F, 0x08),
+    (0x00, 0x40, 0x4D), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xAD, 0xAD, 0xAD), (0x15, 0x5F, 0xD9), (0x42, 0x40, 0xFF), (0x75, 0x27, 0xFE),
+    (0xA0, 0x1A, 0xCC), (0xB7, 0x1E, 0x7B), (0xB5, 0x31, 0x20), (0x99, 0x4E, 0x00),
+    (0x6B, 0x6D, 0x00), (0x38, 0x87, 0x00), (0x0C, 0x93, 0x00), (0x00, 0x8F, 0x32),
+    (0x00, 0x7C, 0x8D), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFF, 0xFE, 0xFF), (0x64, 0xB0, 0xFF), (0x92, 0x90, 0xFF), (0xC6, 0x76, 0xFF),
+    (0xF3, 0x6A, 0xFF), (0xFE, 0x6E, 0xCC), (0xFE, 0x81, 0x70), (0xEA, 0x9E, 0x22),
+    (0xBC, 0xBE, 0x00), (0x88, 0xD8, 0x00), (0x5C, 0xE4, 0x30), (0x45, 0xE0, 0x82),
+    (0x48, 0xCD, 0xDE), (0x4F, 0x4F, 0x4F), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFF, 0xFE, 0xFF), (0xC0, 0xDF, 0xFF), (0xD3, 0xD2, 0xFF), (0xE8, 0xC8, 0xFF),
+    (0xFB, 0xC2, 0xFF), (0xFE, 0xC4, 0xEA), (0xFE, 0xCC, 0xC5), (0xF7, 0xD8, 0xA5),
+    (0xE4, 0xE5, 0x94), (0xCF, 0xEF, 0x96), (0xBD, 0xF4, 0xAB), (0xB3, 0xF3, 0xCC),
+    (0xB5, 0xEB, 0xF2), (0xB8, 0xB8, 0xB8), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+//FirebrandX's "Smooth" palette, an average of several NES/Famicom captures
+const PALETTE_SMOOTH_FBX: [(u8, u8, u8); 64] = [
+    (0x6A, 0x6D, 0x6A), (0x00, 0x13, 0x80), (0x1E, 0x00, 0x8A), (0x39, 0x00, 0x7A),
+    (0x55, 0x00, 0x56), (0x5A, 0x00, 0x18), (0x4F, 0x10, 0x00), (0x3D, 0x1C, 0x00),
+    (0x25, 0x32, 0x00), (0x00, 0x3D, 0x00), (0x00, 0x40, 0x00), (0x00, 0x39, 0x24),
+    (0x00, 0x2E, 0x55), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xB9, 0xBC, 0xB9), (0x18, 0x50, 0xC7), (0x4B, 0x30, 0xE3), (0x73, 0x22, 0xD6),
+    (0x95, 0x1F, 0xA9), (0x9D, 0x28, 0x5C), (0x98, 0x37, 0x00), (0x7F, 0x4C, 0x00),
+    (0x5E, 0x64, 0x00), (0x22, 0x77, 0x00), (0x02, 0x7E, 0x02), (0x00, 0x76, 0x45),
+    (0x00, 0x6E, 0x8A), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF), (0x68, 0xA6, 0xFF), (0x8C, 0x9C, 0xFF), (0xB5, 0x86, 0xFF),
+    (0xD9, 0x75, 0xFD), (0xE3, 0x77, 0xB9), (0xE5, 0x8D, 0x68), (0xD4, 0x9D, 0x29),
+    (0xB3, 0xAF, 0x0C), (0x7B, 0xC2, 0x11), (0x55, 0xCA, 0x47), (0x46, 0xCB, 0x81),
+    (0x47, 0xC1, 0xC5), (0x4A, 0x4D, 0x4A), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF), (0xCC, 0xEA, 0xFF), (0xDD, 0xDE, 0xFF), (0xEC, 0xDA, 0xFF),
+    (0xF8, 0xD7, 0xFE), (0xFC, 0xD6, 0xF5), (0xFD, 0xDB, 0xCF), (0xF9, 0xE7, 0xB5),
+    (0xF1, 0xF0, 0xAA), (0xDA, 0xFA, 0xA9), (0xC9, 0xFF, 0xBC), (0xC3, 0xFB, 0xD7),
+    (0xC4, 0xF6, 0xF6), (0xBE, 0xC1, 0xBE), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+//FirebrandX's capture of a Sony PVM monitor at a D93 white point
+const PALETTE_PVM_D93_FBX: [(u8, u8, u8); 64] = [
+    (0x69, 0x6B, 0x63), (0x00, 0x17, 0x74), (0x1E, 0x00, 0x87), (0x34, 0x00, 0x73),
+    (0x56, 0x00, 0x57), (0x5E, 0x00, 0x13), (0x53, 0x1A, 0x00), (0x3B, 0x24, 0x00),
+    (0x24, 0x30, 0x00), (0x06, 0x3A, 0x00), (0x00, 0x3F, 0x00), (0x00, 0x3B, 0x1E),
+    (0x00, 0x33, 0x4E), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xB9, 0xBB, 0xB3), (0x14, 0x53, 0xB9), (0x4D, 0x2C, 0xDA), (0x67, 0x1E, 0xDE),
+    (0x98, 0x18, 0x9C), (0x9D, 0x23, 0x44), (0xA0, 0x3E, 0x00), (0x8D, 0x55, 0x00),
+    (0x65, 0x6D, 0x00), (0x2C, 0x79, 0x00), (0x00, 0x81, 0x00), (0x00, 0x7D, 0x42),
+    (0x00, 0x78, 0x8A), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF), (0x69, 0xA8, 0xFF), (0x96, 0x91, 0xFF), (0xB2, 0x8A, 0xFA),
+    (0xEA, 0x7D, 0xFA), (0xF3, 0x7B, 0xC7), (0xF2, 0x8E, 0x59), (0xE6, 0xAD, 0x27),
+    (0xD7, 0xC8, 0x05), (0x90, 0xDF, 0x07), (0x64, 0xE5, 0x3C), (0x45, 0xE2, 0x7D),
+    (0x48, 0xD5, 0xD9), (0x4E, 0x50, 0x48), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+    (0xFF, 0xFF, 0xFF), (0xD2, 0xEA, 0xFF), (0xE2, 0xE2, 0xFF), (0xE9, 0xD8, 0xFF),
+    (0xF5, 0xD2, 0xFF), (0xF8, 0xD9, 0xEA), (0xFA, 0xDE, 0xB9), (0xF9, 0xE8, 0x9B),
+    (0xF3, 0xF2, 0x8C), (0xD3, 0xFA, 0x91), (0xB8, 0xFC, 0xA8), (0xAE, 0xFA, 0xCA),
+    (0xCA, 0xF3, 0xF3), (0xBE, 0xC0, 0xB8), (0x00, 0x00, 0x00), (0x00, 0x00, 0x00),
+];
+
+//The RGB PPUs (2C03/2C05) output 3 bits per channel, written here as octal RGB triplets
+const PALETTE_2C03_RGB: [u16; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420,
+    0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630,
+    0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750,
+    0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772,
+    0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PalettePreset {
+    Ntsc2C02,
+    Fceux,
+    Nestopia,
+    SmoothFbx,
+    PvmD93Fbx,
+    Rgb2C03,
+    //Generated from the composite signal with the default NtscSettings
+    NtscGenerated,
+    //Same, decoded the way a PAL TV sees a 2C07
+    PalGenerated,
+}
+
+impl PalettePreset {
+    pub const ALL: [PalettePreset; 8] = [PalettePreset::Ntsc2C02, PalettePreset::Fceux,
+        PalettePreset::Nestopia, PalettePreset::SmoothFbx, PalettePreset::PvmD93Fbx,
+        PalettePreset::Rgb2C03, PalettePreset::NtscGenerated, PalettePreset::PalGenerated];
+
+    pub fn next(self) -> PalettePreset {
+        let i = PalettePreset::ALL.iter().position(|p| *p == self).unwrap();
+        PalettePreset::ALL[(i + 1) % PalettePreset::ALL.len()]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    //Degrees to rotate every hue by
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    pub gamma: f32,
+}
+
+impl Default for NtscSettings {
+    fn default() -> NtscSettings {
+        NtscSettings { hue: 0.0, saturation: 1.0, contrast: 1.0, brightness: 0.0, gamma: 1.8 }
+    }
+}
+
+pub fn in_color_phase(color: u16, phase: usize) -> bool {
+    (color as usize + phase) % SIGNAL_PHASES < SIGNAL_PHASES / 2
+}
+
+pub fn composite_signal_level(pixel: u16, phase: usize) -> f32 {
+    //Normalized (black = 0, white = 1) composite level the PPU outputs for a pixel
+    // (emphasis << 6 | colour number) at one of the 12 subcarrier phases
+    let color = pixel & 0x0F;
+    let emphasis = pixel >> 6;
+    let level = if color > 13 { 1 } else { ((pixel >> 4) & 0b11) as usize };
+    let mut low = SIGNAL_LOW[level];
+    let mut high = SIGNAL_HIGH[level];
+    //Colour 0 is a flat grey at the high level, colours $D-$F are flat at the low level
+    if color == 0 {
+        low = high;
+    }
+    if color > 12 {
+        high = low;
+    }
+    let mut signal = if in_color_phase(color, phase) { high } else { low };
+    let emphasized = (emphasis & 0b001 > 0 && in_color_phase(0, phase))
+        || (emphasis & 0b010 > 0 && in_color_phase(4, phase))
+        || (emphasis & 0b100 > 0 && in_color_phase(8, phase));
+    if emphasized && color < 0x0E {
+        signal *= EMPHASIS_ATTENUATION;
+    }
+    (signal - SIGNAL_BLACK) / (SIGNAL_WHITE - SIGNAL_BLACK)
+}
+
+pub fn subcarrier_angle(phase: usize, hue: f32) -> f32 {
+    PI * (phase as f32 + NTSC_HUE_OFFSET + hue / 30.0) / 6.0
+}
+
+pub fn yiq_to_rgb(y: f32, i: f32, q: f32, gamma: f32) -> (u8, u8, u8) {
+    let gamma_fix = |c: f32| if c <= 0.0 { 0.0 } else { c.powf(2.2 / gamma) };
+    let to_u8 = |c: f32| (gamma_fix(c) * 255.0).clamp(0.0, 255.0) as u8;
+    (
+        to_u8(y + 0.946882 * i + 0.623557 * q),
+        to_u8(y - 0.274788 * i - 0.635691 * q),
+        to_u8(y - 1.108545 * i + 1.709007 * q),
+    )
+}
+
+//All 512 colours the PPU can output, indexed by emphasis bits (BGR) << 6 | colour number
+pub struct Palette {
+    colors: [(u8, u8, u8); 512],
+}
+
+impl Palette {
+    pub fn from_preset(preset: PalettePreset) -> Palette {
+        match preset {
+            PalettePreset::Ntsc2C02 => Palette::from_base_colors(&PALETTE_2C02),
+            PalettePreset::Fceux => Palette::from_base_colors(&PALETTE_FCEUX),
+            PalettePreset::Nestopia => Palette::from_base_colors(&PALETTE_NESTOPIA),
+            PalettePreset::SmoothFbx => Palette::from_base_colors(&PALETTE_SMOOTH_FBX),
+            PalettePreset::PvmD93Fbx => Palette::from_base_colors(&PALETTE_PVM_D93_FBX),
+            PalettePreset::NtscGenerated => Palette::generate_ntsc(&NtscSettings::default()),
+            PalettePreset::PalGenerated => Palette::generate_pal(&NtscSettings::default()),
+            PalettePreset::Rgb2C03 => {
+                let mut base = [(0, 0, 0); 64];
+                for (color, rgb) in base.iter_mut().zip(PALETTE_2C03_RGB.iter()) {
+                    let level = |shift: u16| (((rgb >> shift) & 0b111) * 255 / 7) as u8;
+                    *color = (level(6), level(3), level(0));
+                }
+                Palette::from_base_colors(&base)
+            }
+        }
+    }
+
+    pub fn from_base_colors(base: &[(u8, u8, u8); 64]) -> Palette {
+        //Derives the emphasized variants from the 64 base colours
+        let mut colors = [(0, 0, 0); 512];
+        for (i, entry) in colors.iter_mut().enumerate() {
+            let (r, g, b) = base[i & 0b111111];
+            let emphasis = i >> 6;
+            //The black columns ($xE/$xF) are not affected by emphasis
+            if emphasis == 0 || i & 0b1110 == 0b1110 {
+                *entry = (r, g, b);
+                continue;
+            }
+            //A channel gets darker whenever any of the other two channels is emphasized
+            let attenuate = |channel: u8, own_bit: usize| -> u8 {
+                if emphasis & !own_bit == 0 { channel } else { (channel as f32 * EMPHASIS_ATTENUATION) as u8 }
+            };
+            *entry = (attenuate(r, 0b001), attenuate(g, 0b010), attenuate(b, 0b100));
+        }
+        Palette { colors }
+    }
+
+    pub fn generate_ntsc(settings: &NtscSettings) -> Palette {
+        Palette::decode_composite(settings, NTSC_HUE_OFFSET)
+    }
+
+    pub fn generate_pal(settings: &NtscSettings) -> Palette {
+        Palette::decode_composite(settings, PAL_HUE_OFFSET)
+    }
+
+    fn decode_composite(settings: &NtscSettings, hue_offset: f32) -> Palette {
+        //Decodes one full subcarrier cycle of every colour back into YIQ, like a TV would
+        let mut colors = [(0, 0, 0); 512];
+        for (pixel, entry) in colors.iter_mut().enumerate() {
+            let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+            for phase in 0..SIGNAL_PHASES {
+                let level = composite_signal_level(pixel as u16, phase);
+                let angle = PI * (phase as f32 + hue_offset + settings.hue / 30.0) / 6.0;
+                y += level;
+                i += level * angle.cos();
+                q += level * angle.sin();
+            }
+            let count = SIGNAL_PHASES as f32;
+            y = y / count * settings.contrast + settings.brightness;
+            i = i / count * settings.saturation;
+            q = q / count * settings.saturation;
+            *entry = yiq_to_rgb(y, i, q, settings.gamma);
+        }
+        Palette { colors }
+    }
+
+    pub fn from_pal_bytes(bytes: &[u8]) -> Result<Palette, String> {
+        match bytes.len() {
+            PAL_FILE_SIZE => {
+                let mut base = [(0, 0, 0); 64];
+                for (color, rgb) in base.iter_mut().zip(bytes.chunks(3)) {
+                    *color = (rgb[0], rgb[1], rgb[2]);
+                }
+                Ok(Palette::from_base_colors(&base))
+            }
+            PAL_FILE_WITH_EMPHASIS_SIZE => {
+                let mut colors = [(0, 0, 0); 512];
+                for (color, rgb) in colors.iter_mut().zip(bytes.chunks(3)) {
+                    *color = (rgb[0], rgb[1], rgb[2]);
+                }
+                Ok(Palette { colors })
+            }
+            len => Err(format!("A .pal file has to be {} or {} bytes long, got {}",
+                               PAL_FILE_SIZE, PAL_FILE_WITH_EMPHASIS_SIZE, len))
+        }
+    }
+
+    pub fn from_file(filepath: &str) -> Result<Palette, String> {
+        let bytes = fs::read(filepath).map_err(|e| format!("Can't read {}: {}", filepath, e))?;
+        Palette::from_pal_bytes(&bytes)
+    }
+
+    pub fn to_pal_bytes(&self) -> Vec<u8> {
+        //Always exports the 1536 byte variant, so the emphasis colours survive the round trip
+        let mut bytes = Vec::with_capacity(PAL_FILE_WITH_EMPHASIS_SIZE);
+        for (r, g, b) in self.colors.iter() {
+            bytes.extend_from_slice(&[*r, *g, *b]);
+        }
+        bytes
+    }
+
+    pub fn save(&self, filepath: &str) -> Result<(), String> {
+        fs::write(filepath, self.to_pal_bytes()).map_err(|e| format!("Can't write {}: {}", filepath, e))
+    }
+
+    pub fn get_rgb_color(&self, index: u16) -> (u8, u8, u8) {
+        self.colors[(index & 0x1FF) as usize]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base_colour_pal_file_gets_emphasis_derived() {
+        let bytes: Vec<u8> = (0..PAL_FILE_SIZE).map(|i| (i % 251) as u8).collect();
+        let palette = Palette::from_pal_bytes(&bytes).unwrap();
+        assert_eq!(palette.get_rgb_color(0x01), (3, 4, 5));
+        //Red emphasis darkens green and blue, and leaves the black columns alone
+        let (r, g, b) = palette.get_rgb_color(0b001 << 6 | 0x01);
+        assert_eq!((r, g, b), (3, (4.0 * EMPHASIS_ATTENUATION) as u8, (5.0 * EMPHASIS_ATTENUATION) as u8));
+        assert_eq!(palette.get_rgb_color(0b001 << 6 | 0x0E), palette.get_rgb_color(0x0E));
+    }
+
+    #[test]
+    fn full_pal_file_round_trips() {
+        let bytes: Vec<u8> = (0..PAL_FILE_WITH_EMPHASIS_SIZE).map(|i| (i * 7 % 256) as u8).collect();
+        let palette = Palette::from_pal_bytes(&bytes).unwrap();
+        assert_eq!(palette.get_rgb_color(0x1FF), (bytes[1533], bytes[1534], bytes[1535]));
+        assert_eq!(palette.to_pal_bytes(), bytes);
+    }
+
+    #[test]
+    fn wrong_pal_file_size_is_an_error() {
+        assert!(Palette::from_pal_bytes(&[0; PAL_FILE_SIZE - 1]).is_err());
+        assert!(Palette::from_pal_bytes(&[0; PAL_FILE_WITH_EMPHASIS_SIZE + 3]).is_err());
+        assert!(Palette::from_pal_bytes(&[]).is_err());
+    }
+
+    #[test]
+    fn presets_cycle_through_all_of_them() {
+        let mut preset = PalettePreset::Ntsc2C02;
+        for _ in 0..PalettePreset::ALL.len() {
+            Palette::from_preset(preset);
+            preset = preset.next();
+        }
+        assert_eq!(preset, PalettePreset::Ntsc2C02);
+    }
+
+    #[test]
+    fn generated_palettes_have_black_and_white_where_the_ppu_does() {
+        for palette in [Palette::generate_ntsc(&NtscSettings::default()),
+                        Palette::generate_pal(&NtscSettings::default())].iter() {
+            assert_eq!(palette.get_rgb_color(0x0F), (0, 0, 0));
+            assert_eq!(palette.get_rgb_color(0x1D), (0, 0, 0));
+            for white in [0x20, 0x30].iter() {
+                let (r, g, b) = palette.get_rgb_color(*white);
+                assert!(r >= 0xF0 && g >= 0xF0 && b >= 0xF0, "${:02X} is ({}, {}, {})", white, r, g, b);
+            }
+        }
+    }
+
+    #[test]
+    fn hue_rotates_colours_but_not_greys() {
+        let normal = Palette::generate_ntsc(&NtscSettings::default());
+        let rotated = Palette::generate_ntsc(&NtscSettings { hue: 30.0, ..NtscSettings::default() });
+        for row in 0..4 {
+            for column in [0x0, 0xD].iter() {
+                let color = row << 4 | column;
+                assert_eq!(normal.get_rgb_color(color), rotated.get_rgb_color(color));
+            }
+            for column in 0x1..=0xC {
+                let color = row << 4 | column;
+                assert_ne!(normal.get_rgb_color(color), rotated.get_rgb_color(color), "${:02X}", color);
+            }
+        }
+        //30 degrees is one colour column, so $x2 takes $x1's place
+        assert_eq!(rotated.get_rgb_color(0x12), normal.get_rgb_color(0x11));
+    }
+
+    #[test]
+    fn saved_palette_loads_back() {
+        let palette = Palette::generate_ntsc(&NtscSettings { saturation: 1.3, gamma: 2.2, ..NtscSettings::default() });
+        let path = std::env::temp_dir().join("rustness_saved_palette_loads_back.pal");
+        let path = path.to_str().unwrap();
+        palette.save(path).unwrap();
+        let loaded = Palette::from_file(path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(loaded.to_pal_bytes(), palette.to_pal_bytes());
+    }
+}
@@ -1,351 +1,1282 @@
-use std::cell::RefCell;
-use std::rc::Rc;
-use crate::nes::palette::get_rgb_color;
-use piston_window::{PistonWindow, Image};
-use opengl_graphics::OpenGL;
-use crate::nes::mem::Mem;
-use std::borrow::Borrow;
+use crate::nes::cartridge::Cartridge;
+use crate::nes::region::Region;
+use crate::nes::palette::SIGNAL_PHASES;
+use std::sync::Arc;
 
-type Tile = [[u8; 8]; 8];
+pub type Tile = [[u8; 8]; 8];
+
+//One of the sprites picked by sprite evaluation, with its pattern row already fetched
+#[derive(Clone, Copy)]
+struct LineSprite {
+    x: u8,
+    attr: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+//Copy of the PPU's internal state for debuggers and other tools
+#[derive(Clone)]
+pub struct PpuState {
+    pub v: u16,
+    pub t: u16,
+    pub fine_x: u8,
+    pub w: bool,
+    pub scanline: i32,
+    pub dot: u16,
+    pub frame_count: u64,
+    pub ctrl: u8,
+    pub mask: u8,
+    //As a $2002 read would return it, minus the open bus bits
+    pub status: u8,
+    pub oam_addr: u8,
+    pub oam: [u8; 256],
+    pub secondary_oam: [u8; 32],
+    pub palette_ram: [u8; 32],
+    //$2000-$2FFF: all four nametables with their attribute tables
+    pub nametables: Vec<u8>,
+    //Both pattern tables ($0000 and $1000) as 512 tiles of 2 bit pixels
+    pub tiles: Arc<Vec<Tile>>,
+    //All four nametables drawn like the PPU addresses them: $2000 $2400 on top, $2800 $2C00 below
+    pub nametable_image: Arc<Vec<u16>>,
+}
+
+impl PpuState {
+    pub fn scroll_origin(&self) -> (u32, u32) {
+        //Top left corner of the next frame's picture inside the 512x480 nametable layout, taken from t
+        let x = ((self.t >> 10) & 0b1) as u32 * 256 + (self.t & 0x1F) as u32 * 8 + self.fine_x as u32;
+        let y = ((self.t >> 11) & 0b1) as u32 * 240 + ((self.t >> 5) & 0x1F) as u32 * 8
+            + ((self.t >> 12) & 0b111) as u32;
+        (x, y)
+    }
+
+    fn palette_color(&self, palette: u8, pixel: u8) -> u16 {
+        let index = if pixel == 0 { 0 } else { ((palette & 0b111) << 2) | pixel };
+        (self.palette_ram[index as usize] & 0b111111) as u16
+    }
+
+    pub fn render_pattern_table(&self, table: u8, palette: u8) -> Vec<u16> {
+        //All 256 tiles of one pattern table in a 16x16 grid, coloured with one of the 8 palettes
+        let mut image = vec![0; PATTERN_TABLE_SIZE * PATTERN_TABLE_SIZE];
+        for tile_no in 0..256 {
+            let tile = self.tiles[table as usize * 256 + tile_no];
+            let (tile_x, tile_y) = ((tile_no % 16) * 8, (tile_no / 16) * 8);
+            for (row, pixels) in tile.iter().enumerate() {
+                for (col, pixel) in pixels.iter().enumerate() {
+                    image[(tile_y + row) * PATTERN_TABLE_SIZE + tile_x + col] = self.palette_color(palette, *pixel);
+                }
+            }
+        }
+        image
+    }
+
+    pub fn render_sprite(&self, sprite: usize) -> Vec<u16> {
+        //One OAM entry as it would be drawn (8x8 sprites only fill the top half of the 8x16 image)
+        let mut image = vec![self.palette_color(0, 0); SPRITE_VIEW_WIDTH * SPRITE_VIEW_HEIGHT];
+        let (tile, attr) = (self.oam[sprite * 4 + 1] as usize, self.oam[sprite * 4 + 2]);
+        let tiles = if self.ctrl & 0b00100000 > 0 {
+            let table = (tile & 0b1) * 256;
+            vec![self.tiles[table + (tile & 0xFE)], self.tiles[table + (tile | 0b1)]]
+        } else {
+            vec![self.tiles[((self.ctrl >> 3) & 0b1) as usize * 256 + tile]]
+        };
+        let height = tiles.len() * 8;
+        for y in 0..height {
+            for x in 0..8 {
+                let src_y = if attr & 0b10000000 > 0 { height - 1 - y } else { y };
+                let src_x = if attr & 0b01000000 > 0 { 7 - x } else { x };
+                let pixel = tiles[src_y / 8][src_y % 8][src_x];
+                if pixel != 0 {
+                    image[y * SPRITE_VIEW_WIDTH + x] = self.palette_color(4 + (attr & 0b11), pixel);
+                }
+            }
+        }
+        image
+    }
+}
 
 pub struct Ppu {
-    pub mem: Rc<RefCell<Mem>>,
-    gl: opengl_graphics::GlGraphics,
-    canvas: im::ImageBuffer<im::Rgba<u8>, Vec<u8>>,
-    texture: opengl_graphics::Texture,
-    img: Image,
-    chr_tiles0: [Tile; 256],
-    chr_tiles1: [Tile; 256],
-    nametable0: [Tile; 960],
-    nametable1: [Tile; 960],
-    nametable2: [Tile; 960],
-    nametable3: [Tile; 960],
-    bg_palette0: [(u8, u8, u8); 4],
-    bg_palette1: [(u8, u8, u8); 4],
-    bg_palette2: [(u8, u8, u8); 4],
-    bg_palette3: [(u8, u8, u8); 4],
-    pallete_per_tile0: [u8; 960],
-    pallete_per_tile1: [u8; 960],
-    pallete_per_tile2: [u8; 960],
-    pallete_per_tile3: [u8; 960],
+    vram: [u8; 0x4000],
+    oam: [u8; 256],
+    val_to_write_to_vram: u8,
+    read_buffer: u8,
+    ppu_stat: u8,
+    nmi_occured: bool,
+    nmi_output: bool,
+    ppu_ctrl: u8,
+    trigger_nmi: bool,
+    //Loopy's internal registers: current and temporary VRAM address (yyy NN YYYYY XXXXX),
+    // fine X scroll and the first/second write toggle shared by $2005 and $2006
+    v: u16,
+    t: u16,
+    fine_x: u8,
+    w: bool,
+    oam_adr: u8,
+    ppu_mask: u8,
+    ppu_latch: u8,
+    ppu_latch_age: [u8; 8],
+    //Finished pixels as emphasis bits << 6 | colour number, ready for the 512 entry palette
+    frame: Vec<u16>,
     current_scanline: i32,
     cycles_total: u64,
+    //Counts finished frames, bumped when vblank starts and the picture is complete
+    frame_count: u64,
+    //Subcarrier phase at dot 0 of the current line and of line 0 of this frame, for the NTSC filter
+    line_phase: usize,
+    frame_phase: usize,
     cycles_for_current_scanline: u16,
-    triggered_nmi_this_scanline: bool,
+    odd_frame: bool,
+    suppress_vblank: bool,
+    region: Region,
+    bg_next_tile_id: u8,
+    bg_next_tile_attr: u8,
+    bg_next_tile_lsb: u8,
+    bg_next_tile_msb: u8,
+    bg_shifter_pattern_lo: u16,
+    bg_shifter_pattern_hi: u16,
+    bg_shifter_attr_lo: u16,
+    bg_shifter_attr_hi: u16,
+    secondary_oam: [u8; 32],
+    line_sprites: [LineSprite; 64],
+    line_sprite_oam_index: [u8; 64],
+    line_sprite_count: usize,
+    //Draws every sprite on a line instead of the first 8 (gets rid of flicker, not accurate)
+    pub no_sprite_limit: bool,
+    //Debug view of the nametables, only the tiles touched since the last refresh get redrawn
+    nametable_image: Arc<Vec<u16>>,
+    dirty_nametable_tiles: Vec<bool>,
+    //Pattern tiles written through $2007, and the cartridge's CHR generation the image was drawn with
+    dirty_chr_tiles: Vec<bool>,
+    chr_generation: u64,
+    nametable_image_dirty: bool,
 }
 
-const CYCLES_PER_SCANLINE: u16 = 340;
-const CHR_0_X_Y: (u32, u32) = (700, 0);
-const CHR_1_X_Y: (u32, u32) = (828, 0);
-const NAMETABLE_0_X_Y: (u32, u32) = (700, 128);
-const NAMETABLE_1_X_Y: (u32, u32) = (700 + 256, 128);
-const NAMETABLE_2_X_Y: (u32, u32) = (700 + 256, 128 + 240);
-const NAMETABLE_3_X_Y: (u32, u32) = (700, 128 + 240);
-const SCREEN_X_Y: (u32, u32) = (0, 0);
+const CYCLES_PER_SCANLINE: u16 = 341;
+//The NTSC colour subcarrier moves on by 8 of its 12 phases every dot
+const SUBCARRIER_PHASES_PER_DOT: usize = 8;
+//Roughly 600ms worth of frames before an undriven PPU latch bit fades back to 0
+const PPU_LATCH_DECAY_FRAMES: u8 = 36;
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+//Sizes of the debug viewer images
+pub const PATTERN_TABLE_SIZE: usize = 128;
+pub const NAMETABLES_WIDTH: usize = 512;
+pub const NAMETABLES_HEIGHT: usize = 480;
+pub const SPRITE_VIEW_WIDTH: usize = 8;
+pub const SPRITE_VIEW_HEIGHT: usize = 16;
+const NAMETABLE_TILES: usize = 960;
 
-impl Ppu {
-    pub fn new(mem: &Rc<RefCell<Mem>>, window: &mut PistonWindow, opengl: OpenGL,
-               (width, height): (u32, u32)) -> Ppu {
-        let mut gl = opengl_graphics::GlGraphics::new(opengl);
+fn palette_index(addr: u16) -> usize {
+    //32 bytes mirrored all over $3F00-$3FFF, and the sprite palettes' first entries
+    // ($3F10/$3F14/$3F18/$3F1C) are shared with the background ones
+    let mut index = addr & 0x1F;
+    if index >= 0x10 && index & 0b11 == 0 {
+        index -= 0x10;
+    }
+    0x3F00 + index as usize
+}
 
-        let mut canvas = im::ImageBuffer::new(width, height);
-        let mut texture = opengl_graphics::Texture::from_image(&canvas, &opengl_graphics::TextureSettings::new());
-        let img = Image::new().rect(graphics::rectangle::rectangle_by_corners(0.0, 0.0, width as f64, height as f64));
+impl Ppu {
+    pub fn new(region: Region) -> Ppu {
         Ppu {
-            mem: Rc::clone(mem),
-            gl,
-            canvas,
-            texture,
-            img,
-            chr_tiles0: [[[0; 8]; 8]; 256],
-            chr_tiles1: [[[0; 8]; 8]; 256],
-            nametable0: [[[0; 8]; 8]; 960],
-            nametable1: [[[0; 8]; 8]; 960],
-            nametable2: [[[0; 8]; 8]; 960],
-            nametable3: [[[0; 8]; 8]; 960],
-            bg_palette0: [(0, 0, 0); 4],
-            bg_palette1: [(0, 0, 0); 4],
-            bg_palette2: [(0, 0, 0); 4],
-            bg_palette3: [(0, 0, 0); 4],
-            pallete_per_tile0: [0; 960],
-            pallete_per_tile1: [0; 960],
-            pallete_per_tile2: [0; 960],
-            pallete_per_tile3: [0; 960],
-            current_scanline: -1,
+            vram: [0; 0x4000],
+            oam: [0; 256],
+            val_to_write_to_vram: 0,
+            read_buffer: 0,
+            ppu_stat: 0,
+            nmi_occured: false,
+            nmi_output: false,
+            ppu_ctrl: 0,
+            trigger_nmi: false,
+            v: 0,
+            t: 0,
+            fine_x: 0,
+            w: false,
+            oam_adr: 0,
+            ppu_mask: 0,
+            ppu_latch: 0,
+            ppu_latch_age: [0; 8],
+            frame: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            current_scanline: region.pre_render_scanline(),
             cycles_total: 0,
+            frame_count: 0,
+            line_phase: 0,
+            frame_phase: 0,
             cycles_for_current_scanline: 0,
-            triggered_nmi_this_scanline: true,
+            odd_frame: false,
+            suppress_vblank: false,
+            region,
+            bg_next_tile_id: 0,
+            bg_next_tile_attr: 0,
+            bg_next_tile_lsb: 0,
+            bg_next_tile_msb: 0,
+            bg_shifter_pattern_lo: 0,
+            bg_shifter_pattern_hi: 0,
+            bg_shifter_attr_lo: 0,
+            bg_shifter_attr_hi: 0,
+            secondary_oam: [0xFF; 32],
+            line_sprites: [LineSprite { x: 0, attr: 0, pattern_lo: 0, pattern_hi: 0 }; 64],
+            line_sprite_oam_index: [0; 64],
+            line_sprite_count: 0,
+            no_sprite_limit: false,
+            nametable_image: Arc::new(vec![0; NAMETABLES_WIDTH * NAMETABLES_HEIGHT]),
+            dirty_nametable_tiles: vec![true; 4 * NAMETABLE_TILES],
+            dirty_chr_tiles: vec![false; 512],
+            chr_generation: 0,
+            nametable_image_dirty: true,
         }
     }
 
-    pub fn emulate(&mut self, cycles: u8) {
-        for i in 0..cycles {
-            if self.current_scanline >= 0 && self.current_scanline < 240
-                && self.cycles_for_current_scanline < 256 {
-                let (nametable_x, nametable_y) = match self.mem.borrow_mut().get_nametable_index() {
-                    0 => NAMETABLE_0_X_Y,
-                    1 => NAMETABLE_1_X_Y,
-                    2 => NAMETABLE_2_X_Y,
-                    3 => NAMETABLE_3_X_Y,
-                    _ => (0, 0)
-                };
-                let scroll_x = self.mem.borrow_mut().get_scroll_x() as u32;
-                let scroll_y = self.mem.borrow_mut().get_scroll_y() as u32;
-                let pixel = self.canvas.get_pixel(
-                    ((self.cycles_for_current_scanline as u32 + scroll_x) % 512) + nametable_x,
-                    ((self.current_scanline as u32 + scroll_y) % 480) + nametable_y,
-                );
-                let target_x = self.cycles_for_current_scanline as u32 * 2;
-                let target_y = self.current_scanline as u32 * 2;
-                let px_clone = pixel.clone();
-                self.canvas.put_pixel(target_x, target_y, px_clone);
-                self.canvas.put_pixel(target_x + 1, target_y, px_clone);
-                self.canvas.put_pixel(target_x + 1, target_y + 1, px_clone);
-                self.canvas.put_pixel(target_x, target_y + 1, px_clone);
-            }
-
-            self.cycles_total = self.cycles_total + 1;
-            self.cycles_for_current_scanline += 1;
-            if self.cycles_for_current_scanline >= CYCLES_PER_SCANLINE {
-                self.cycles_for_current_scanline -= CYCLES_PER_SCANLINE;
-                self.current_scanline += 1;
-                self.triggered_nmi_this_scanline = false;
-            }
-        }
-
-        if self.current_scanline == 240 && !self.triggered_nmi_this_scanline {
-            self.mem.borrow_mut().set_nmi_occured(true);
-            let nmi_out = self.mem.borrow_mut().get_nmi_output();
-            if nmi_out {
-                self.mem.borrow_mut().set_trigger_nmi(true);
-            }
-            self.triggered_nmi_this_scanline = true;
-        }
-        if self.current_scanline == 261 {
-            self.mem.borrow_mut().set_nmi_occured(false);
-            self.current_scanline = 0;
-        }
-    }
-
-    fn prepare_bg_stuff(&mut self) {
-        let universal_bg_color = self.get_universal_bg_color();
-        self.bg_palette0 = self.get_palette(0x3F01, universal_bg_color);
-        self.bg_palette1 = self.get_palette(0x3F05, universal_bg_color);
-        self.bg_palette2 = self.get_palette(0x3F09, universal_bg_color);
-        self.bg_palette3 = self.get_palette(0x3F0D, universal_bg_color);
-
-        self.pallete_per_tile0 = self.parse_attr_to_tiles(0x23C0);
-        self.pallete_per_tile1 = self.parse_attr_to_tiles(0x27C0);
-        self.pallete_per_tile2 = self.parse_attr_to_tiles(0x2BC0);
-        self.pallete_per_tile3 = self.parse_attr_to_tiles(0x2FC0);
-
-        self.chr_tiles0 = self.render_chr(0x0000, CHR_0_X_Y.0, CHR_0_X_Y.1);
-        self.chr_tiles1 = self.render_chr(0x1000, CHR_1_X_Y.0, CHR_1_X_Y.1);
-
-        self.nametable0 = self.render_nametable(0x2000,
-                                                NAMETABLE_0_X_Y.0,
-                                                NAMETABLE_0_X_Y.1,
-                                                self.pallete_per_tile0);
-        self.nametable1 = self.render_nametable(0x2400,
-                                                NAMETABLE_1_X_Y.0,
-                                                NAMETABLE_1_X_Y.1,
-                                                self.pallete_per_tile1);
-        self.nametable2 = self.render_nametable(0x2800,
-                                                NAMETABLE_2_X_Y.0,
-                                                NAMETABLE_2_X_Y.1,
-                                                self.pallete_per_tile2);
-        self.nametable3 = self.render_nametable(0x2C00,
-                                                NAMETABLE_3_X_Y.0,
-                                                NAMETABLE_3_X_Y.1,
-                                                self.pallete_per_tile3);
-    }
-
-    fn draw_all_sprites_in_a_very_stupid_manner(&mut self) {
-        if self.mem.borrow_mut().draw_sprites() {
-            let chr_for_sprites = match self.mem.borrow_mut().get_oam_chr_number() {
-                0 => self.chr_tiles0,
-                1 => self.chr_tiles1,
-                _ => self.chr_tiles0
-            };
-            let universal_bg_color = self.get_universal_bg_color();
-            let sprite_palette0 = self.get_palette(0x3F11, universal_bg_color);
-            let sprite_palette1 = self.get_palette(0x3F15, universal_bg_color);
-            let sprite_palette2 = self.get_palette(0x3F19, universal_bg_color);
-            let sprite_palette3 = self.get_palette(0x3F1D, universal_bg_color);
-
-            //64 sprites, 4 bytes each
-            for tile_no in 0..64 {
-                //TODO: self.mem.borrow_mut().should_use_big_sprites()
-                let oam_adr_for_tile = tile_no * 4;
-                let y = self.mem.borrow_mut().oam[oam_adr_for_tile as usize];
-                if y < 0xEF {
-                    let tile = chr_for_sprites[self.mem.borrow_mut().oam[(oam_adr_for_tile + 1) as usize] as usize];
-                    let attr = self.mem.borrow_mut().oam[(oam_adr_for_tile + 2) as usize];
-                    let x = self.mem.borrow_mut().oam[(oam_adr_for_tile + 3) as usize];
-                    if attr & 0b100000 == 0 {
-                        let pal_no = attr & 0b11;
-                        let horiz_flip = attr & 0b01000000 > 0;
-                        let vert_flip = attr & 0b10000000 > 0;
-                        for i in 0..8 {
-                            for j in 0..8 {
-                                let mut x_final = (x as u32 + j) * 2;
-                                if horiz_flip {
-                                    x_final = (x as u32 + (7 - j)) * 2;
-                                }
-                                let mut y_final = (y as u32 + i) * 2;
-                                if vert_flip {
-                                    y_final = (y as u32 + (7 - i)) * 2;
-                                }
-                                if x_final < 512 && y_final < 480 {
-                                    let color = tile[i as usize][j as usize];
-                                    if color > 0 {
-                                        let (r, g, b) = match pal_no {
-                                            0 => sprite_palette0[color as usize],
-                                            1 => sprite_palette1[color as usize],
-                                            2 => sprite_palette2[color as usize],
-                                            3 => sprite_palette3[color as usize],
-                                            _ => sprite_palette0[color as usize]
-                                        };
-                                        self.canvas.put_pixel(x_final, y_final, im::Rgba([r, g, b, 255]));
-                                        self.canvas.put_pixel(x_final + 1, y_final, im::Rgba([r, g, b, 255]));
-                                        self.canvas.put_pixel(x_final + 1, y_final + 1, im::Rgba([r, g, b, 255]));
-                                        self.canvas.put_pixel(x_final, y_final + 1, im::Rgba([r, g, b, 255]));
-                                    }
-                                }
-                            }
-                        }
+    pub fn should_increment_by_1(&mut self) -> bool {
+        return (self.ppu_ctrl & 0b100) == 0
+    }
+    pub fn use_chr_0(&mut self) -> bool {
+        return (self.ppu_ctrl & 0b10000) == 0
+    }
+    pub fn get_nmi_enable(&mut self) -> bool {
+        (self.ppu_ctrl & 128) > 0
+    }
+    pub fn set_nmi_output(&mut self, set: bool) {
+        self.nmi_output = set;
+    }
+    pub fn get_nmi_output(&mut self) -> bool {
+        self.nmi_output
+    }
+    pub fn set_nmi_occured(&mut self, set: bool) {
+        self.nmi_occured = set;
+    }
+    pub fn get_nmi_occured(&mut self) -> bool {
+        self.nmi_occured
+    }
+    pub fn set_trigger_nmi(&mut self, set: bool) {
+        self.trigger_nmi = set;
+    }
+    pub fn get_trigger_nmi(&mut self) -> bool {
+        self.trigger_nmi
+    }
+    pub fn get_oam_chr_number(&mut self) -> u8 {
+        (self.ppu_ctrl & 0b1000) >> 3
+    }
+    pub fn set_sprite_0_hit(&mut self, hit: bool) {
+        if hit {
+            self.ppu_stat |= 0b01000000;
+        } else {
+            self.ppu_stat &= 0b10111111;
+        }
+    }
+    pub fn should_use_big_sprites(&mut self) -> bool {
+        self.ppu_ctrl & 0b00100000 > 0
+    }
+    pub fn draw_sprites(&mut self) -> bool {
+        self.ppu_mask & 0b00010000 > 0
+    }
+    pub fn draw_background(&mut self) -> bool {
+        self.ppu_mask & 0b00001000 > 0
+    }
+    pub fn draw_sprites_left(&mut self) -> bool {
+        self.ppu_mask & 0b00000100 > 0
+    }
+    pub fn draw_background_left(&mut self) -> bool {
+        self.ppu_mask & 0b00000010 > 0
+    }
+    pub fn use_grayscale(&mut self) -> bool {
+        self.ppu_mask & 0b00000001 > 0
+    }
+    pub fn get_emphasis(&mut self) -> u8 {
+        //Always in red, green, blue order, whatever the PPU's own bit order is
+        let emphasis = self.ppu_mask >> 5;
+        if self.region.swaps_red_green_emphasis() {
+            (emphasis & 0b100) | ((emphasis & 0b01) << 1) | ((emphasis & 0b10) >> 1)
+        } else {
+            emphasis
+        }
+    }
+    pub fn get_region(&self) -> Region {
+        self.region
+    }
+    pub fn set_sprite_overflow(&mut self, overflow: bool) {
+        if overflow {
+            self.ppu_stat |= 0b00100000;
+        } else {
+            self.ppu_stat &= 0b11011111;
+        }
+    }
+    pub fn refresh_ppu_latch(&mut self, val: u8, mask: u8) {
+        //Only the bits that were actually driven get their decay timer reset
+        self.ppu_latch = (self.ppu_latch & !mask) | (val & mask);
+        for (bit, age) in self.ppu_latch_age.iter_mut().enumerate() {
+            if mask & (1 << bit) > 0 {
+                *age = 0;
+            }
+        }
+    }
+    pub fn decay_ppu_latch(&mut self) {
+        //Called once per frame
+        for (bit, age) in self.ppu_latch_age.iter_mut().enumerate() {
+            if *age < PPU_LATCH_DECAY_FRAMES {
+                *age += 1;
+            } else {
+                self.ppu_latch &= !(1 << bit);
+            }
+        }
+    }
+
+    fn get_status(&self) -> u8 {
+        if self.nmi_occured {
+            self.ppu_stat | 128
+        } else {
+            self.ppu_stat & 0b01111111
+        }
+    }
+
+    pub fn read_register(&mut self, ppu_reg: u16, cart: &mut Cartridge) -> u8 {
+        match ppu_reg {
+            2 => {
+                if self.current_scanline == self.region.vblank_scanline() {
+                    match self.cycles_for_current_scanline {
+                        //One dot before vblank starts: the flag reads clear and won't get set this frame
+                        1 => self.suppress_vblank = true,
+                        //On the same dot or right after: the flag reads set, but the NMI never happens
+                        2..=3 => self.set_trigger_nmi(false),
+                        _ => {}
                     }
                 }
+                let data = self.get_status();
+                self.set_nmi_occured(false);
+                self.w = false;
+                //Only the top 3 bits are driven, the rest comes from the latch
+                self.refresh_ppu_latch(data, 0b11100000);
+                self.ppu_latch
+            }
+            7 => {
+                let adr = self.v & 0x3FFF;
+                let ret_val = if adr >= 0x3F00 {
+                    //Palette reads skip the buffer (only 6 bits are driven), but the buffer still
+                    // gets filled with the nametable byte hiding underneath the palette
+                    self.read_buffer = self.read_vram(cart, adr - 0x1000);
+                    let data = self.read_vram(cart, adr);
+                    self.refresh_ppu_latch(data, 0b00111111);
+                    self.ppu_latch
+                } else {
+                    let data = self.read_buffer;
+                    self.read_buffer = self.read_vram(cart, adr);
+                    self.refresh_ppu_latch(data, 0xFF);
+                    data
+                };
+                self.increment_vram_adr();
+                ret_val
             }
+            4 => {
+                let data = self.peek_oam_data();
+                self.refresh_ppu_latch(data, 0xFF);
+                data
+            }
+            //Write-only registers just return whatever is left on the PPU latch
+            _ => self.ppu_latch
+        }
+    }
+
+    fn peek_oam_data(&self) -> u8 {
+        //Secondary OAM gets cleared during the first 64 dots, which reads back as $FF
+        if self.is_rendering() && (1..=64).contains(&self.cycles_for_current_scanline) {
+            0xFF
+        } else {
+            self.oam[self.oam_adr as usize]
+        }
+    }
 
-//        self.mem.borrow_mut().set_sprite_0_hit(true);
+    pub fn peek_register(&self, ppu_reg: u16, cart: &Cartridge) -> u8 {
+        match ppu_reg {
+            2 => (self.get_status() & 0b11100000) | (self.ppu_latch & 0b00011111),
+            4 => self.peek_oam_data(),
+            7 => {
+                let adr = self.v & 0x3FFF;
+                if adr >= 0x3F00 {
+                    (self.ppu_latch & 0b11000000) | (self.peek_vram(cart, adr) & 0b00111111)
+                } else {
+                    self.read_buffer
+                }
+            }
+            _ => self.ppu_latch
         }
     }
 
-    pub fn render(&mut self, r: piston_window::RenderArgs) {
-        self.prepare_bg_stuff();
-        self.draw_all_sprites_in_a_very_stupid_manner();
-        self.texture.update(&self.canvas);
+    pub fn write_register(&mut self, ppu_reg: u16, val: u8, cart: &mut Cartridge) {
+        self.refresh_ppu_latch(val, 0xFF);
+        match ppu_reg {
+            0 => {
+                let nmi_was_enabled = self.get_nmi_output();
+                if (self.ppu_ctrl ^ val) & 0b00010000 > 0 {
+                    //Another background pattern table, every tile looks different now
+                    self.mark_nametable_image_dirty();
+                }
+                self.ppu_ctrl = val;
+                self.set_nmi_output(self.ppu_ctrl >= 128);
+                if !nmi_was_enabled && self.get_nmi_output() && self.nmi_occured {
+                    //Enabling NMI during vblank fires another one straight away
+                    self.set_trigger_nmi(true);
+                } else if !self.get_nmi_output() && self.current_scanline == self.region.vblank_scanline()
+                    && (2..=3).contains(&self.cycles_for_current_scanline) {
+                    //Disabling it right as vblank starts cancels the NMI
+                    self.set_trigger_nmi(false);
+                }
+                self.t = (self.t & !0x0C00) | ((val as u16 & 0b11) << 10);
+            }
+            1 => {
+                self.ppu_mask = val;
+            }
+            3 => {
+                self.oam_adr = val;
+            }
+            4 => {
+                if self.is_rendering() {
+                    //Writes are dropped while rendering, but OAMADDR still gets bumped like a sprite step
+                    self.oam_adr = self.oam_adr.wrapping_add(4);
+                } else {
+                    //Bits 2-4 of the attribute byte don't exist in OAM
+                    let data = if self.oam_adr & 0b11 == 2 { val & 0b11100011 } else { val };
+                    self.oam[self.oam_adr as usize] = data;
+                    self.oam_adr = self.oam_adr.wrapping_add(1);
+                }
+            }
+            5 => {
+                if !self.w {
+                    self.t = (self.t & !0x001F) | (val as u16 >> 3);
+                    self.fine_x = val & 0b111;
+                } else {
+                    self.t = (self.t & !0x73E0) | ((val as u16 & 0b111) << 12)
+                        | ((val as u16 & 0b11111000) << 2);
+                }
+                self.w = !self.w;
+            }
+            6 => {
+                if !self.w {
+                    //Bit 14 of t gets cleared by the first write
+                    self.t = (self.t & 0x00FF) | ((val as u16 & 0b111111) << 8);
+                } else {
+                    self.t = (self.t & 0xFF00) | (val as u16);
+                    self.v = self.t;
+                }
+                self.w = !self.w;
+            }
+            7 => {
+                self.val_to_write_to_vram = val;
+                self.write_vram(cart, self.v & 0x3FFF, self.val_to_write_to_vram);
+                self.increment_vram_adr();
+            }
+            _ => ()
+        }
+    }
 
-        let c = self.gl.draw_begin(r.viewport());
-        graphics::clear([0.0, 0.0, 0.0, 1.0], &mut self.gl);
+    pub fn read_vram(&mut self, cart: &mut Cartridge, addr: u16) -> u8 {
+        match addr {
+            0..=0x1FFF => {
+                cart.read_chr(addr)
+            }
+            _ => self.peek_vram(cart, addr)
+        }
+    }
 
-        graphics::image(&self.texture, c.transform, &mut self.gl);
+    pub fn peek_vram(&self, cart: &Cartridge, addr: u16) -> u8 {
+        match addr {
+            0..=0x1FFF => {
+                cart.peek_chr(addr)
+            }
+            0x2000..=0x2FFF => {
+                self.vram[addr as usize]
+            }
+            0x3000..=0x3EFF => {
+                self.vram[(addr - 0x1000) as usize]
+            }
+            0x3F00..=0x3FFF => {
+                self.vram[palette_index(addr)]
+            }
+            _ => self.vram[addr as usize]
+        }
+    }
 
-        self.gl.draw_end();
+    pub fn write_vram(&mut self, cart: &mut Cartridge, addr: u16, val: u8) {
+        match addr {
+            0..=0x1FFF => {
+                if cart.write_chr(addr, val) {
+                    self.dirty_chr_tiles[(addr >> 4) as usize] = true;
+                    self.nametable_image_dirty = true;
+                }
+            }
+            0x2000..=0x2FFF => {
+                self.vram[addr as usize] = val;
+                self.mark_nametable_byte_dirty(addr);
+            }
+            0x3000..=0x3EFF => {
+                self.vram[(addr - 0x1000) as usize] = val;
+                self.mark_nametable_byte_dirty(addr - 0x1000);
+            }
+            0x3F00..=0x3FFF => {
+                let index = palette_index(addr);
+                //Only the background palettes show up in the nametables
+                if index < 0x3F10 && self.vram[index] != val {
+                    self.mark_nametable_image_dirty();
+                }
+                self.vram[index] = val;
+            }
+            _ => {
+                panic!("Out of range (write {:X} to {:X})", val, addr);
+            }
+        }
     }
 
-    fn parse_attr_to_tiles(&mut self, base_adr: u16) -> [u8; 960] {
-        let mut pal_num_per_tile: [u8; 960] = [0; 960];
-        for row_num in 0..30 {
-            for col_num in 0..32 {
-                let i = row_num * 32 + col_num;
-                let quad_block_number = ((row_num / 4) * 8) + (col_num / 4);
-                let block_number = ((row_num / 2) * 16) + (col_num / 2);
-                let block_number_in_quad_block_bit = (((row_num / 2) % 2) << 1) + (block_number % 2);
-                let quad_bit = self.mem.borrow_mut().read_vram((base_adr + (quad_block_number as u16)));
-                let pal_number = (quad_bit >> ((block_number_in_quad_block_bit) * 2)) & 0b11;
-                pal_num_per_tile[i as usize] = pal_number;
+    fn mark_nametable_byte_dirty(&mut self, addr: u16) {
+        let (nametable, offset) = (((addr >> 10) & 0b11) as usize, (addr & 0x3FF) as usize);
+        let first_tile = nametable * NAMETABLE_TILES;
+        if offset < NAMETABLE_TILES {
+            self.dirty_nametable_tiles[first_tile + offset] = true;
+        } else {
+            //Attribute bytes cover 4x4 tiles (only 4x2 in the last row)
+            let (start_col, start_row) = ((offset - 0x3C0) % 8 * 4, (offset - 0x3C0) / 8 * 4);
+            for row in start_row..(start_row + 4).min(30) {
+                for col in start_col..start_col + 4 {
+                    self.dirty_nametable_tiles[first_tile + row * 32 + col] = true;
+                }
             }
         }
-        pal_num_per_tile
+        self.nametable_image_dirty = true;
     }
 
-    fn get_universal_bg_color(&mut self) -> (u8, u8, u8) {
-        let col_num = self.mem.borrow_mut().read_vram(0x3F00);
-        get_rgb_color(col_num)
+    fn mark_nametable_image_dirty(&mut self) {
+        for dirty in self.dirty_nametable_tiles.iter_mut() {
+            *dirty = true;
+        }
+        self.nametable_image_dirty = true;
+    }
+
+    pub fn refresh_nametable_image(&mut self, cart: &Cartridge) {
+        //Redraws the tiles of the nametable view that changed since the last call
+        if cart.get_chr_generation() != self.chr_generation {
+            self.chr_generation = cart.get_chr_generation();
+            self.mark_nametable_image_dirty();
+        }
+        if !self.nametable_image_dirty {
+            return;
+        }
+        let table = ((self.ppu_ctrl >> 4) & 0b1) as usize;
+        let tiles = cart.get_chr_tiles();
+        let image = Arc::make_mut(&mut self.nametable_image);
+        for (tile_no, dirty) in self.dirty_nametable_tiles.iter_mut().enumerate() {
+            let (nametable, tile_index) = (tile_no / NAMETABLE_TILES, tile_no % NAMETABLE_TILES);
+            let base = 0x2000 + nametable * 0x400;
+            let pattern = table * 256 + self.vram[base + tile_index] as usize;
+            if !*dirty && !self.dirty_chr_tiles[pattern] {
+                continue;
+            }
+            *dirty = false;
+            let (col, row) = (tile_index % 32, tile_index / 32);
+            //Every attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+            let attr = self.vram[base + 0x3C0 + (row / 4) * 8 + col / 4];
+            let palette = (attr >> (((row & 0b10) << 1) | (col & 0b10))) & 0b11;
+            let (start_x, start_y) = ((nametable & 0b1) * 256 + col * 8, (nametable >> 1) * 240 + row * 8);
+            for (y, pixels) in tiles[pattern].iter().enumerate() {
+                for (x, pixel) in pixels.iter().enumerate() {
+                    let index = if *pixel == 0 { 0 } else { (palette << 2) | pixel };
+                    image[(start_y + y) * NAMETABLES_WIDTH + start_x + x] =
+                        (self.vram[palette_index(0x3F00 + index as u16)] & 0b111111) as u16;
+                }
+            }
+        }
+        for dirty in self.dirty_chr_tiles.iter_mut() {
+            *dirty = false;
+        }
+        self.nametable_image_dirty = false;
     }
 
-    fn get_palette(&mut self, base_adr: u16, ubg: (u8, u8, u8))
-                   -> [(u8, u8, u8); 4] {
-        let mut pal = [(0, 0, 0); 4];
-        pal[0] = ubg;
-        for i in 0..3 {
-            let col_num = self.mem.borrow_mut().read_vram(base_adr + i);
-            pal[(i + 1) as usize] = get_rgb_color(col_num);
+    pub fn emulate(&mut self, cycles: u8, cart: &mut Cartridge) {
+        for _ in 0..cycles {
+            self.step_dot(cart);
         }
-        pal
     }
 
-    fn render_nametable(&mut self, base_adr: u16, render_start_x: u32,
-                        render_start_y: u32, palette_per_tile: [u8; 960]) -> [Tile; 960] {
-        //FIXME: Only recalculate if there were changes in Nametable
-        //Parse out nametable 0 (960 bytes; 32 tiles wide; 30 tiles high)
-        let mut nametable = [[[0; 8]; 8]; 960];
+    fn step_dot(&mut self, cart: &mut Cartridge) {
+        let scanline = self.current_scanline;
+        let dot = self.cycles_for_current_scanline;
+        let visible_line = scanline < 240;
+        let pre_render_line = scanline == self.region.pre_render_scanline();
 
-        for rows in 0..30u16 {
-            for cols in 0..32u16 {
-                //Get the tile and save it
-                let index = ((rows * 32) + cols);
-                let tile_no = self.mem.borrow_mut().read_vram(index + base_adr);
-                if self.mem.borrow_mut().use_chr_0() {
-                    nametable[index as usize] = self.chr_tiles0[tile_no as usize];
+        if scanline == self.region.vblank_scanline() && dot == 1 {
+            self.frame_count += 1;
+            if !self.suppress_vblank {
+                self.set_nmi_occured(true);
+                if self.get_nmi_output() {
+                    self.set_trigger_nmi(true);
+                }
+            }
+            self.suppress_vblank = false;
+        }
+        if pre_render_line && dot == 1 {
+            self.set_nmi_occured(false);
+            self.set_sprite_overflow(false);
+            self.set_sprite_0_hit(false);
+            if self.rendering_enabled() && self.oam_adr >= 8 {
+                //2C02 bug: starting to render with OAMADDR >= 8 copies that 8 byte row over the first one
+                let row = (self.oam_adr & 0xF8) as usize;
+                self.oam.copy_within(row..row + 8, 0);
+            }
+        }
+        if self.rendering_enabled() && (visible_line || pre_render_line) && (257..=320).contains(&dot) {
+            //Sprite pattern fetches leave OAMADDR at 0
+            self.oam_adr = 0;
+        }
+        if self.rendering_enabled() && (visible_line || pre_render_line) {
+            self.fetch_background(cart, dot);
+            if pre_render_line && (280..=304).contains(&dot) {
+                self.copy_vertical_scroll();
+            }
+            if dot == 257 {
+                if visible_line {
+                    self.evaluate_sprites();
+                    self.fetch_sprite_patterns(cart);
                 } else {
-                    nametable[index as usize] = self.chr_tiles1[tile_no as usize];
+                    //Sprites never show up on the first visible line
+                    self.line_sprite_count = 0;
                 }
+            }
+        }
+        if visible_line && (1..=256).contains(&dot) {
+            self.output_pixel((dot - 1) as u32, scanline as u32);
+        }
 
-                //Render it out
-                let tile_start_x = render_start_x + ((cols as u32) * 8);
-                let tile_start_y = render_start_y + ((rows as u32) * 8);
-                let pal_for_tile = match palette_per_tile[index as usize] {
-                    0 => { self.bg_palette0 }
-                    1 => { self.bg_palette1 }
-                    2 => { self.bg_palette2 }
-                    3 => { self.bg_palette3 }
-                    _ => [(0, 0, 0); 4]
-                };
-                for i in 0..8 {
-                    for j in 0..8 {
-                        let x = (tile_start_x + j);
-                        let y = (tile_start_y + i);
-                        let color = nametable[index as usize][i as usize][j as usize];
-                        let (r, g, b) = pal_for_tile[color as usize];
-                        self.canvas.put_pixel(x, y, im::Rgba([r, g, b, 255]));
+        self.cycles_total += 1;
+        self.cycles_for_current_scanline += 1;
+        //NTSC odd frames skip the last dot of the pre-render line when rendering is on
+        let skip_dot = self.region.skips_odd_frame_dot() && self.odd_frame;
+        let line_length = if pre_render_line && skip_dot && self.rendering_enabled() {
+            CYCLES_PER_SCANLINE - 1
+        } else {
+            CYCLES_PER_SCANLINE
+        };
+        if self.cycles_for_current_scanline >= line_length {
+            self.cycles_for_current_scanline = 0;
+            self.current_scanline += 1;
+            self.line_phase = (self.line_phase + line_length as usize * SUBCARRIER_PHASES_PER_DOT) % SIGNAL_PHASES;
+            if self.current_scanline == self.region.scanlines_per_frame() {
+                self.decay_ppu_latch();
+                self.odd_frame = !self.odd_frame;
+                self.current_scanline = 0;
+                self.frame_phase = self.line_phase;
+            }
+        }
+    }
+
+    fn rendering_enabled(&self) -> bool {
+        self.ppu_mask & 0b00011000 > 0
+    }
+
+    fn is_rendering(&self) -> bool {
+        self.rendering_enabled()
+            && (self.current_scanline < 240 || self.current_scanline == self.region.pre_render_scanline())
+    }
+
+    fn increment_vram_adr(&mut self) {
+        if self.is_rendering() {
+            //$2007 access while rendering bumps v through both scroll increments at once
+            self.increment_coarse_x();
+            self.increment_y();
+        } else if self.should_increment_by_1() {
+            self.v = self.v.wrapping_add(1) & 0x7FFF;
+        } else {
+            self.v = self.v.wrapping_add(32) & 0x7FFF;
+        }
+    }
+
+    fn fetch_background(&mut self, cart: &mut Cartridge, dot: u16) {
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background_shifters();
+                    self.bg_next_tile_id = self.read_vram(cart, 0x2000 | (self.v & 0x0FFF));
+                }
+                2 => {
+                    let v = self.v;
+                    let mut attr = self.read_vram(cart, 0x23C0 | (v & 0x0C00)
+                        | ((v >> 4) & 0x38) | ((v >> 2) & 0x07));
+                    //Every attribute byte covers 4x4 tiles, 2 bits per 2x2 quadrant
+                    if v & 0b1000000 > 0 {
+                        attr >>= 4;
+                    }
+                    if v & 0b10 > 0 {
+                        attr >>= 2;
                     }
+                    self.bg_next_tile_attr = attr & 0b11;
                 }
+                4 => {
+                    let adr = self.bg_pattern_adr();
+                    self.bg_next_tile_lsb = self.read_vram(cart, adr);
+                }
+                6 => {
+                    let adr = self.bg_pattern_adr() + 8;
+                    self.bg_next_tile_msb = self.read_vram(cart, adr);
+                }
+                7 => {
+                    self.increment_coarse_x();
+                }
+                _ => {}
             }
         }
-        nametable
+        if dot == 256 {
+            self.increment_y();
+        }
+        if dot == 257 {
+            self.load_background_shifters();
+            self.copy_horizontal_scroll();
+        }
     }
 
-    fn render_chr(&mut self, adr_base: u16, render_start_x: u32,
-                  render_start_y: u32) -> [Tile; 256] {
-        //FIXME: Only recalculate if there were changes in CHR
-        //Parse out chr data and just render it to screen in b&w
-        let mut ret_tiles = [[[0; 8]; 8]; 256];
+    fn bg_pattern_adr(&mut self) -> u16 {
+        let table: u16 = if self.use_chr_0() { 0x0000 } else { 0x1000 };
+        let fine_y = (self.v >> 12) & 0b111;
+        table + ((self.bg_next_tile_id as u16) << 4) + fine_y
+    }
 
-        //Render CHR1 ($0000-$0FFF)
-        for tile_no in 0..256 {
-            //256 tiles, each takes up 16 bytes, and consists of two pictures of 8 bytes
-            // that are later on added together to form the resulting picture
-            let mut low_bits: [u8; 8] = [0; 8];
-            let mut high_bits: [u8; 8] = [0; 8];
-
-            for i in 0..8 {
-                let low_bit_adr = adr_base + (tile_no * 16) + i;
-                let high_bit_adr = low_bit_adr + 8;
-                low_bits[i as usize] = self.mem.borrow_mut().read_vram(low_bit_adr);
-                high_bits[i as usize] = self.mem.borrow_mut().read_vram(high_bit_adr);
-            }
-
-            //Render it out
-            let tile_start_x = render_start_x + (tile_no as u32 % 16) * 8;
-            let tile_start_y = render_start_y + (tile_no as u32 / 16) * 8;
-            for i in 0..8 {
-                for j in 0..8 {
-                    let x = (tile_start_x + (7 - j));
-                    let y = (tile_start_y + i);
-                    let color =
-                        (
-                            ((low_bits[i as usize] >> (j as u8)) & 0b1) |
-                                (((high_bits[i as usize] >> (j as u8)) & 0b1) << 1)
-                        );
-                    ret_tiles[tile_no as usize][i as usize][(7 - j) as usize] = color;
-                    let (r, g, b) = self.bg_palette0[color as usize];
-                    self.canvas.put_pixel(x, y, im::Rgba([r, g, b, 255]));
+    fn shift_background(&mut self) {
+        self.bg_shifter_pattern_lo <<= 1;
+        self.bg_shifter_pattern_hi <<= 1;
+        self.bg_shifter_attr_lo <<= 1;
+        self.bg_shifter_attr_hi <<= 1;
+    }
+
+    fn load_background_shifters(&mut self) {
+        //The next tile goes into the low byte, the current one is being shifted out of the high one
+        self.bg_shifter_pattern_lo = (self.bg_shifter_pattern_lo & 0xFF00) | self.bg_next_tile_lsb as u16;
+        self.bg_shifter_pattern_hi = (self.bg_shifter_pattern_hi & 0xFF00) | self.bg_next_tile_msb as u16;
+        let attr_lo = if self.bg_next_tile_attr & 0b01 > 0 { 0xFF } else { 0x00 };
+        let attr_hi = if self.bg_next_tile_attr & 0b10 > 0 { 0xFF } else { 0x00 };
+        self.bg_shifter_attr_lo = (self.bg_shifter_attr_lo & 0xFF00) | attr_lo;
+        self.bg_shifter_attr_hi = (self.bg_shifter_attr_hi & 0xFF00) | attr_hi;
+    }
+
+    fn increment_coarse_x(&mut self) {
+        if self.v & 0x001F == 31 {
+            //Wrap around into the horizontally adjacent nametable
+            self.v &= !0x001F;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+        } else {
+            self.v &= !0x7000;
+            let mut coarse_y = (self.v & 0x03E0) >> 5;
+            if coarse_y == 29 {
+                //Last row of tiles, switch to the vertically adjacent nametable
+                coarse_y = 0;
+                self.v ^= 0x0800;
+            } else if coarse_y == 31 {
+                //Row 30 and 31 are attributes, so wrap without switching nametables
+                coarse_y = 0;
+            } else {
+                coarse_y += 1;
+            }
+            self.v = (self.v & !0x03E0) | (coarse_y << 5);
+        }
+    }
+
+    fn copy_horizontal_scroll(&mut self) {
+        self.v = (self.v & !0x041F) | (self.t & 0x041F);
+    }
+
+    fn copy_vertical_scroll(&mut self) {
+        self.v = (self.v & !0x7BE0) | (self.t & 0x7BE0);
+    }
+
+    fn sprite_height(&mut self) -> i32 {
+        if self.should_use_big_sprites() { 16 } else { 8 }
+    }
+
+    fn sprite_in_range(&mut self, y: u8) -> bool {
+        let row = self.current_scanline - y as i32;
+        row >= 0 && row < self.sprite_height()
+    }
+
+    fn evaluate_sprites(&mut self) {
+        //Finds the sprites for the next scanline (sprites are drawn one line below their Y)
+        self.secondary_oam = [0xFF; 32];
+        self.line_sprite_count = 0;
+        let mut found = 0;
+        let mut n = 0;
+        while n < 64 && found < 8 {
+            let y = self.oam[n * 4];
+            self.secondary_oam[found * 4] = y;
+            if self.sprite_in_range(y) {
+                self.secondary_oam[found * 4..found * 4 + 4].copy_from_slice(&self.oam[n * 4..n * 4 + 4]);
+                self.add_line_sprite(n);
+                found += 1;
+            }
+            n += 1;
+        }
+
+        if self.no_sprite_limit {
+            for extra in n..64 {
+                if self.sprite_in_range(self.oam[extra * 4]) {
+                    self.add_line_sprite(extra);
+                }
+            }
+        }
+
+        //Hardware bug: after 8 sprites the PPU also increments the byte index within each entry,
+        // so it compares tile numbers, attributes and X positions against the scanline as well
+        let mut m = 0;
+        while n < 64 {
+            if self.sprite_in_range(self.oam[n * 4 + m]) {
+                self.set_sprite_overflow(true);
+                break;
+            }
+            n += 1;
+            m = (m + 1) & 0b11;
+        }
+    }
+
+    fn add_line_sprite(&mut self, oam_index: usize) {
+        self.line_sprites[self.line_sprite_count] = LineSprite {
+            x: self.oam[oam_index * 4 + 3],
+            attr: self.oam[oam_index * 4 + 2],
+            pattern_lo: 0,
+            pattern_hi: 0,
+        };
+        self.line_sprite_oam_index[self.line_sprite_count] = oam_index as u8;
+        self.line_sprite_count += 1;
+    }
+
+    fn fetch_sprite_patterns(&mut self, cart: &mut Cartridge) {
+        for i in 0..self.line_sprite_count {
+            let sprite = self.line_sprites[i];
+            let oam_adr = self.line_sprite_oam_index[i] as usize * 4;
+            let (y, tile) = (self.oam[oam_adr], self.oam[oam_adr + 1]);
+            let height = self.sprite_height() as u16;
+            let mut row = (self.current_scanline - y as i32) as u16;
+            if sprite.attr & 0b10000000 > 0 {
+                //Flips across the whole sprite, so 8x16 sprites swap their halves too
+                row = height - 1 - row;
+            }
+            let adr = if height == 16 {
+                //8x16 sprites pick the table with bit 0 of the tile number and use an even/odd tile pair
+                let table = (tile as u16 & 0b1) * 0x1000;
+                let mut tile_no = tile as u16 & 0xFE;
+                if row >= 8 {
+                    tile_no += 1;
+                    row -= 8;
+                }
+                table + (tile_no << 4) + row
+            } else {
+                let table = self.get_oam_chr_number() as u16 * 0x1000;
+                table + ((tile as u16) << 4) + row
+            };
+            let mut lo = self.read_vram(cart, adr);
+            let mut hi = self.read_vram(cart, adr + 8);
+            if sprite.attr & 0b01000000 > 0 {
+                lo = lo.reverse_bits();
+                hi = hi.reverse_bits();
+            }
+            self.line_sprites[i].pattern_lo = lo;
+            self.line_sprites[i].pattern_hi = hi;
+        }
+    }
+
+    fn sprite_pixel(&mut self, x: u32) -> Option<(u8, u8, bool, bool)> {
+        //Returns the colour, palette, behind-background bit and whether it's sprite 0
+        // for the frontmost opaque sprite
+        for (i, sprite) in self.line_sprites[..self.line_sprite_count].iter().enumerate() {
+            let offset = x as i32 - sprite.x as i32;
+            if (0..8).contains(&offset) {
+                let p0 = (sprite.pattern_lo >> (7 - offset)) & 0b1;
+                let p1 = (sprite.pattern_hi >> (7 - offset)) & 0b1;
+                let pixel = (p1 << 1) | p0;
+                if pixel != 0 {
+                    let is_sprite_0 = self.line_sprite_oam_index[i] == 0;
+                    return Some((pixel, sprite.attr & 0b11, sprite.attr & 0b00100000 > 0, is_sprite_0));
                 }
             }
         }
-        ret_tiles
+        None
+    }
+
+    fn sprite_0_hit_possible(&mut self, x: u32) -> bool {
+        //Both layers have to be on, and the hit never happens on the last pixel
+        // or inside the leftmost 8 pixels when either layer is clipped there
+        let both_enabled = self.ppu_mask & 0b00011000 == 0b00011000;
+        let left_clipped = self.ppu_mask & 0b00000110 != 0b00000110;
+        both_enabled && x != 255 && !(x < 8 && left_clipped)
+    }
+
+    fn output_pixel(&mut self, x: u32, y: u32) {
+        let mut pixel = 0;
+        let mut palette = 0;
+        if self.draw_background() && (x >= 8 || self.draw_background_left()) {
+            let mux = 0x8000 >> self.fine_x;
+            let p0 = (self.bg_shifter_pattern_lo & mux > 0) as u8;
+            let p1 = (self.bg_shifter_pattern_hi & mux > 0) as u8;
+            pixel = (p1 << 1) | p0;
+            let a0 = (self.bg_shifter_attr_lo & mux > 0) as u8;
+            let a1 = (self.bg_shifter_attr_hi & mux > 0) as u8;
+            palette = (a1 << 1) | a0;
+        }
+        let sprite = if self.draw_sprites() && (x >= 8 || self.draw_sprites_left()) {
+            self.sprite_pixel(x)
+        } else {
+            None
+        };
+        if let Some((_, _, _, true)) = sprite {
+            if pixel != 0 && self.sprite_0_hit_possible(x) {
+                self.set_sprite_0_hit(true);
+            }
+        }
+        let palette_adr = match sprite {
+            //Sprites lose to opaque background pixels only when their priority bit is set
+            Some((sprite_pixel, sprite_palette, behind_bg, _)) if pixel == 0 || !behind_bg => {
+                0x3F10 + ((sprite_palette << 2) | sprite_pixel) as u16
+            }
+            //Transparent pixels all share the universal background colour
+            _ if pixel == 0 => 0x3F00,
+            _ => 0x3F00 + ((palette << 2) | pixel) as u16
+        };
+        let mut color_no = self.vram[palette_adr as usize] & 0b111111;
+        if self.use_grayscale() {
+            color_no &= 0b110000;
+        }
+        self.frame[y as usize * SCREEN_WIDTH + x as usize] =
+            ((self.get_emphasis() as u16) << 6) | color_no as u16;
+    }
+
+    pub fn get_frame_count(&self) -> u64 {
+        self.frame_count
+    }
+    pub fn get_scanline(&self) -> i32 {
+        self.current_scanline
+    }
+    pub fn get_frame_phase(&self) -> usize {
+        //A full frame moves the subcarrier 4 phases on, the skipped dot takes 8 of them back,
+        // so with rendering on this flips between two values and the artifacts shimmer in place
+        self.frame_phase
+    }
+
+    pub fn get_frame(&self) -> &[u16] {
+        &self.frame
+    }
+
+    pub fn get_state(&self, cart: &Cartridge) -> PpuState {
+        //The nametable image is as of the last refresh_nametable_image
+        let mut palette_ram = [0; 32];
+        for (i, entry) in palette_ram.iter_mut().enumerate() {
+            *entry = self.vram[palette_index(0x3F00 + i as u16)];
+        }
+        PpuState {
+            v: self.v,
+            t: self.t,
+            fine_x: self.fine_x,
+            w: self.w,
+            scanline: self.current_scanline,
+            dot: self.cycles_for_current_scanline,
+            frame_count: self.frame_count,
+            ctrl: self.ppu_ctrl,
+            mask: self.ppu_mask,
+            status: self.get_status() & 0b11100000,
+            oam_addr: self.oam_adr,
+            oam: self.oam,
+            secondary_oam: self.secondary_oam,
+            palette_ram,
+            nametables: self.vram[0x2000..0x3000].to_vec(),
+            tiles: Arc::clone(cart.get_chr_tiles()),
+            nametable_image: Arc::clone(&self.nametable_image),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NMI_ENABLE: u8 = 0b10000000;
+    const VBLANK_LINE: i32 = 241;
+
+    fn ppu_at(scanline: i32, dot: u16) -> (Ppu, Cartridge) {
+        let mut ppu = Ppu::new(Region::Ntsc);
+        ppu.current_scanline = scanline;
+        ppu.cycles_for_current_scanline = dot;
+        (ppu, Cartridge::new(vec![0; 0x4000], vec![0; 0x2000]))
+    }
+
+    fn read_status_around_vblank(dot: u16) -> (u8, bool) {
+        //Reads $2002 right before the given dot of the vblank line is run, then finishes
+        // the start of vblank and returns what was read and whether an NMI is waiting
+        let (mut ppu, mut cart) = ppu_at(VBLANK_LINE, 0);
+        ppu.write_register(0, NMI_ENABLE, &mut cart);
+        while ppu.cycles_for_current_scanline < dot {
+            ppu.step_dot(&mut cart);
+        }
+        let status = ppu.read_register(2, &mut cart) & 0b10000000;
+        for _ in 0..10 {
+            ppu.step_dot(&mut cart);
+        }
+        (status, ppu.get_trigger_nmi())
+    }
+
+    #[test]
+    fn status_read_before_vblank_leaves_nmi_alone() {
+        assert_eq!(read_status_around_vblank(0), (0, true));
+    }
+
+    #[test]
+    fn status_read_one_dot_before_vblank_suppresses_flag_and_nmi() {
+        let (mut ppu, mut cart) = ppu_at(VBLANK_LINE, 1);
+        ppu.write_register(0, NMI_ENABLE, &mut cart);
+        assert_eq!(ppu.read_register(2, &mut cart) & 0b10000000, 0);
+        for _ in 0..10 {
+            ppu.step_dot(&mut cart);
+        }
+        assert_eq!(ppu.read_register(2, &mut cart) & 0b10000000, 0);
+        assert!(!ppu.get_trigger_nmi());
+    }
+
+    #[test]
+    fn status_read_as_vblank_starts_cancels_nmi() {
+        assert_eq!(read_status_around_vblank(2), (0b10000000, false));
+        assert_eq!(read_status_around_vblank(3), (0b10000000, false));
+    }
+
+    #[test]
+    fn status_read_after_vblank_started_keeps_nmi() {
+        assert_eq!(read_status_around_vblank(4), (0b10000000, true));
+    }
+
+    #[test]
+    fn disabling_nmi_as_vblank_starts_cancels_it() {
+        for (dot, nmi) in [(2, false), (3, false), (5, true)].iter() {
+            let (mut ppu, mut cart) = ppu_at(VBLANK_LINE, 0);
+            ppu.write_register(0, NMI_ENABLE, &mut cart);
+            while ppu.cycles_for_current_scanline < *dot {
+                ppu.step_dot(&mut cart);
+            }
+            ppu.write_register(0, 0, &mut cart);
+            assert_eq!(ppu.get_trigger_nmi(), *nmi, "PPUCTRL write at dot {}", dot);
+        }
+    }
+
+    #[test]
+    fn enabling_nmi_during_vblank_fires_it() {
+        let (mut ppu, mut cart) = ppu_at(VBLANK_LINE, 0);
+        for _ in 0..20 {
+            ppu.step_dot(&mut cart);
+        }
+        assert!(!ppu.get_trigger_nmi());
+        ppu.write_register(0, NMI_ENABLE, &mut cart);
+        assert!(ppu.get_trigger_nmi());
+    }
+
+    fn pre_render_line_length(odd_frame: bool, mask: u8) -> u16 {
+        let (mut ppu, mut cart) = ppu_at(Region::Ntsc.pre_render_scanline(), 0);
+        ppu.odd_frame = odd_frame;
+        ppu.write_register(1, mask, &mut cart);
+        let mut dots = 0;
+        while ppu.current_scanline != 0 {
+            ppu.step_dot(&mut cart);
+            dots += 1;
+        }
+        dots
+    }
+
+    #[test]
+    fn odd_frames_skip_a_dot_only_while_rendering() {
+        assert_eq!(pre_render_line_length(true, 0b00011000), 340);
+        assert_eq!(pre_render_line_length(false, 0b00011000), 341);
+        assert_eq!(pre_render_line_length(true, 0), 341);
+    }
+
+    fn frame_phases(mask: u8) -> Vec<usize> {
+        let (mut ppu, mut cart) = ppu_at(0, 0);
+        ppu.write_register(1, mask, &mut cart);
+        let mut phases = vec![];
+        while phases.len() < 4 {
+            ppu.step_dot(&mut cart);
+            if ppu.current_scanline == 0 && ppu.cycles_for_current_scanline == 0 {
+                phases.push(ppu.get_frame_phase());
+            }
+        }
+        phases
+    }
+
+    #[test]
+    fn dot_crawl_repeats_every_2_frames_while_rendering_and_every_3_otherwise() {
+        assert_eq!(frame_phases(0b00011000), vec![4, 0, 4, 0]);
+        assert_eq!(frame_phases(0), vec![4, 8, 0, 4]);
+    }
+
+    fn write_vram_through_2007(ppu: &mut Ppu, cart: &mut Cartridge, addr: u16, val: u8) {
+        ppu.write_register(6, (addr >> 8) as u8, cart);
+        ppu.write_register(6, addr as u8, cart);
+        ppu.write_register(7, val, cart);
+    }
+
+    #[test]
+    fn nametable_image_follows_nametable_attribute_palette_and_chr_writes() {
+        //CHR RAM, so the test can change the tiles too
+        let mut cart = Cartridge::new(vec![0; 0x4000], vec![]);
+        let mut ppu = Ppu::new(Region::Ntsc);
+        let pixel = |ppu: &Ppu, cart: &Cartridge, x: usize, y: usize| {
+            ppu.get_state(cart).nametable_image[y * NAMETABLES_WIDTH + x]
+        };
+        //Tile 1 is solid colour 1, the others stay blank
+        for row in 0..8 {
+            write_vram_through_2007(&mut ppu, &mut cart, 0x0010 + row, 0xFF);
+        }
+        write_vram_through_2007(&mut ppu, &mut cart, 0x3F00, 0x0F);
+        write_vram_through_2007(&mut ppu, &mut cart, 0x3F01, 0x16);
+        write_vram_through_2007(&mut ppu, &mut cart, 0x3F05, 0x2A);
+        ppu.refresh_nametable_image(&cart);
+        assert_eq!(pixel(&ppu, &cart, 256 + 8, 0), 0x0F);
+
+        //Second tile of the $2400 nametable
+        write_vram_through_2007(&mut ppu, &mut cart, 0x2401, 1);
+        ppu.refresh_nametable_image(&cart);
+        assert_eq!(pixel(&ppu, &cart, 256 + 8, 0), 0x16);
+
+        //Attribute byte for its quadrant, palette 1
+        write_vram_through_2007(&mut ppu, &mut cart, 0x27C0, 0b01);
+        ppu.refresh_nametable_image(&cart);
+        assert_eq!(pixel(&ppu, &cart, 256 + 8, 0), 0x2A);
+
+        let before = ppu.get_state(&cart);
+        write_vram_through_2007(&mut ppu, &mut cart, 0x3F05, 0x30);
+        ppu.refresh_nametable_image(&cart);
+        assert_eq!(pixel(&ppu, &cart, 256 + 8, 0), 0x30);
+        //Older snapshots keep what they saw
+        assert_eq!(before.nametable_image[256 + 8], 0x2A);
+
+        //Clearing the top row of the tile shows the backdrop there
+        write_vram_through_2007(&mut ppu, &mut cart, 0x0010, 0x00);
+        ppu.refresh_nametable_image(&cart);
+        assert_eq!(pixel(&ppu, &cart, 256 + 8, 0), 0x0F);
+        assert_eq!(pixel(&ppu, &cart, 256 + 8, 1), 0x30);
+    }
+
+    #[test]
+    fn chr_rom_writes_leave_nametable_image_alone() {
+        let (mut ppu, mut cart) = ppu_at(0, 0);
+        ppu.refresh_nametable_image(&cart);
+        write_vram_through_2007(&mut ppu, &mut cart, 0x0010, 0xFF);
+        assert!(!ppu.nametable_image_dirty);
+        assert_eq!(cart.peek_chr(0x0010), 0);
+    }
+
+    const SOLID_TILE: u8 = 1;
+    const SPRITE_0_HIT: u8 = 0b01000000;
+    const SHOW_ALL: u8 = 0b00011110;
+
+    fn sprite_0_hit_dot(mask: u8, x: u8, y: u8) -> Option<(i32, u16)> {
+        //Renders one frame of solid background tiles with a solid sprite 0 on top,
+        // returns the scanline and dot the hit flag got set on
+        let mut chr = vec![0; 0x2000];
+        for row in 0..8 {
+            chr[SOLID_TILE as usize * 16 + row] = 0xFF;
+        }
+        let mut cart = Cartridge::new(vec![0; 0x4000], chr);
+        let mut ppu = Ppu::new(Region::Ntsc);
+        for tile in ppu.vram[0x2000..0x23C0].iter_mut() {
+            *tile = SOLID_TILE;
+        }
+        ppu.oam[..4].copy_from_slice(&[y, SOLID_TILE, 0, x]);
+        ppu.ppu_mask = mask;
+        while ppu.current_scanline != VBLANK_LINE {
+            let (scanline, dot) = (ppu.current_scanline, ppu.cycles_for_current_scanline);
+            ppu.step_dot(&mut cart);
+            if ppu.ppu_stat & SPRITE_0_HIT > 0 {
+                return Some((scanline, dot));
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn sprite_0_hit_lands_on_the_first_overlapping_pixel() {
+        //Sprites show up one line below their Y, and pixel x is output on dot x + 1
+        assert_eq!(sprite_0_hit_dot(SHOW_ALL, 20, 30), Some((31, 21)));
+        assert_eq!(sprite_0_hit_dot(SHOW_ALL, 0, 30), Some((31, 1)));
+    }
+
+    #[test]
+    fn no_sprite_0_hit_at_x_255() {
+        assert_eq!(sprite_0_hit_dot(SHOW_ALL, 254, 30), Some((31, 255)));
+        assert_eq!(sprite_0_hit_dot(SHOW_ALL, 255, 30), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn no_sprite_0_hit_in_clipped_left_column() {
+        let no_background_left = SHOW_ALL & !0b00000010;
+        let no_sprites_left = SHOW_ALL & !0b00000100;
+        //A sprite at x 4 overlaps from pixel 4 on, but with either layer clipped the hit waits for pixel 8
+        assert_eq!(sprite_0_hit_dot(SHOW_ALL, 4, 30), Some((31, 5)));
+        assert_eq!(sprite_0_hit_dot(no_background_left, 4, 30), Some((31, 9)));
+        assert_eq!(sprite_0_hit_dot(no_sprites_left, 4, 30), Some((31, 9)));
+        assert_eq!(sprite_0_hit_dot(no_background_left, 0, 30), None);
+    }
+
+    #[test]
+    fn no_sprite_0_hit_with_a_layer_disabled() {
+        assert_eq!(sprite_0_hit_dot(SHOW_ALL & !0b00001000, 20, 30), None);
+        assert_eq!(sprite_0_hit_dot(SHOW_ALL & !0b00010000, 20, 30), None);
+    }
+
+    #[test]
+    fn sprite_0_hit_clears_at_pre_render_dot_1() {
+        let (mut ppu, mut cart) = ppu_at(261, 0);
+        ppu.set_sprite_0_hit(true);
+        ppu.step_dot(&mut cart);
+        assert!(ppu.ppu_stat & SPRITE_0_HIT > 0);
+        ppu.step_dot(&mut cart);
+        assert_eq!(ppu.ppu_stat & SPRITE_0_HIT, 0);
+    }
+
+    fn read_vram_through_2007(ppu: &mut Ppu, cart: &mut Cartridge, addr: u16) -> u8 {
+        ppu.write_register(6, (addr >> 8) as u8, cart);
+        ppu.write_register(6, addr as u8, cart);
+        ppu.read_register(7, cart)
+    }
+
+    #[test]
+    fn vram_reads_return_the_buffered_byte() {
+        let (mut ppu, mut cart) = ppu_at(0, 0);
+        ppu.vram[0x2000..0x2002].copy_from_slice(&[0x11, 0x22]);
+        ppu.vram[0x2400] = 0x33;
+        read_vram_through_2007(&mut ppu, &mut cart, 0x2400);
+        //The first read after setting the address returns what the previous one buffered
+        assert_eq!(read_vram_through_2007(&mut ppu, &mut cart, 0x2000), 0x33);
+        assert_eq!(ppu.read_register(7, &mut cart), 0x11);
+        assert_eq!(ppu.read_register(7, &mut cart), 0x22);
+    }
+
+    #[test]
+    fn palette_reads_skip_the_buffer_but_fill_it_from_the_nametable_below() {
+        let (mut ppu, mut cart) = ppu_at(0, 0);
+        ppu.vram[0x2F04] = 0x44;
+        write_vram_through_2007(&mut ppu, &mut cart, 0x3F04, 0x2A);
+        assert_eq!(read_vram_through_2007(&mut ppu, &mut cart, 0x3F04) & 0b00111111, 0x2A);
+        assert_eq!(read_vram_through_2007(&mut ppu, &mut cart, 0x2000), 0x44);
+    }
+
+    #[test]
+    fn sprite_palette_backdrop_entries_mirror_the_background_ones() {
+        let (mut ppu, mut cart) = ppu_at(0, 0);
+        for (i, (sprite, background)) in [(0x3F10, 0x3F00), (0x3F14, 0x3F04), (0x3F18, 0x3F08), (0x3F1C, 0x3F0C)]
+            .iter().enumerate() {
+            write_vram_through_2007(&mut ppu, &mut cart, *sprite, 0x10 + i as u8);
+            assert_eq!(read_vram_through_2007(&mut ppu, &mut cart, *background) & 0b00111111, 0x10 + i as u8);
+            write_vram_through_2007(&mut ppu, &mut cart, *background, 0x20 + i as u8);
+            assert_eq!(read_vram_through_2007(&mut ppu, &mut cart, *sprite) & 0b00111111, 0x20 + i as u8);
+        }
+        //The other sprite colours have entries of their own
+        write_vram_through_2007(&mut ppu, &mut cart, 0x3F11, 0x01);
+        write_vram_through_2007(&mut ppu, &mut cart, 0x3F01, 0x02);
+        assert_eq!(read_vram_through_2007(&mut ppu, &mut cart, 0x3F11) & 0b00111111, 0x01);
+    }
+}
+
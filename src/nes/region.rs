@@ -0,0 +1,145 @@
+use crate::nes::palette::PalettePreset;
+use std::path::Path;
+
+//TV system the console was built for. It decides the clocks and the shape of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Ntsc,
+    Pal,
+    //Famiclones (Dendy and friends): PAL frame rate with NTSC-like CPU timing
+    Dendy,
+}
+
+const DOTS_PER_SCANLINE: u32 = 341;
+
+impl Region {
+    pub fn detect(rom_header: &[u8], filepath: &str) -> Region {
+        //NES 2.0 has a proper timing field, plain iNES only has a rarely set PAL bit.
+        // There's no rom database (by hash) to fall back on, so for plain iNES files
+        // the GoodNES/No-Intro region tags in the file name are the last resort. Only the name itself,
+        // a rom sorted into a "Europe" folder can still be an NTSC dump
+        let nes2 = rom_header[7] & 0b1100 == 0b1000;
+        if nes2 {
+            return match rom_header[12] & 0b11 {
+                1 => Region::Pal,
+                3 => Region::Dendy,
+                //2 is "multiple regions", those run fine on NTSC
+                _ => Region::Ntsc,
+            };
+        }
+        if rom_header[9] & 0b1 > 0 {
+            return Region::Pal;
+        }
+        let name = Path::new(filepath).file_name()
+            .map_or(String::new(), |name| name.to_string_lossy().to_lowercase());
+        if ["(e)", "(europe)", "(pal)"].iter().any(|tag| name.contains(tag)) {
+            Region::Pal
+        } else {
+            Region::Ntsc
+        }
+    }
+
+    pub fn default_palette(&self) -> PalettePreset {
+        //Dendy consoles were sold with PAL TVs
+        match self {
+            Region::Ntsc => PalettePreset::Ntsc2C02,
+            Region::Pal | Region::Dendy => PalettePreset::PalGenerated,
+        }
+    }
+
+    pub fn cpu_clock_hz(&self) -> u32 {
+        match self {
+            Region::Ntsc => 1_789_773,
+            Region::Pal => 1_662_607,
+            Region::Dendy => 1_773_448,
+        }
+    }
+
+    pub fn ppu_dots_per_cpu_cycle(&self) -> (u32, u32) {
+        //As a fraction, PAL runs 3.2 dots per CPU cycle
+        match self {
+            Region::Ntsc | Region::Dendy => (3, 1),
+            Region::Pal => (16, 5),
+        }
+    }
+
+    pub fn scanlines_per_frame(&self) -> i32 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    pub fn vblank_scanline(&self) -> i32 {
+        //Dendy adds its 50 extra lines before vblank, PAL adds them to vblank itself
+        match self {
+            Region::Ntsc | Region::Pal => 241,
+            Region::Dendy => 291,
+        }
+    }
+
+    pub fn pre_render_scanline(&self) -> i32 {
+        self.scanlines_per_frame() - 1
+    }
+
+    pub fn skips_odd_frame_dot(&self) -> bool {
+        *self == Region::Ntsc
+    }
+
+    pub fn swaps_red_green_emphasis(&self) -> bool {
+        //The 2C07 and the Dendy PPUs have PPUMASK bits 5 and 6 the other way around
+        *self != Region::Ntsc
+    }
+
+    pub fn frame_rate(&self) -> f64 {
+        let (dots, cycles) = self.ppu_dots_per_cpu_cycle();
+        let dots_per_second = self.cpu_clock_hz() as f64 * dots as f64 / cycles as f64;
+        dots_per_second / (self.scanlines_per_frame() as u32 * DOTS_PER_SCANLINE) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header(flags_7: u8, flags_9: u8, byte_12: u8) -> [u8; 16] {
+        let mut header = [0; 16];
+        header[..4].copy_from_slice(b"NES\x1A");
+        header[7] = flags_7;
+        header[9] = flags_9;
+        header[12] = byte_12;
+        header
+    }
+
+    const NES2: u8 = 0b1000;
+
+    #[test]
+    fn nes2_timing_field_wins() {
+        assert_eq!(Region::detect(&header(NES2, 0, 0), "game (E).nes"), Region::Ntsc);
+        assert_eq!(Region::detect(&header(NES2, 0, 1), "game.nes"), Region::Pal);
+        assert_eq!(Region::detect(&header(NES2, 0, 2), "game.nes"), Region::Ntsc);
+        assert_eq!(Region::detect(&header(NES2, 0, 3), "game.nes"), Region::Dendy);
+    }
+
+    #[test]
+    fn ines_pal_bit() {
+        assert_eq!(Region::detect(&header(0, 1, 0), "game.nes"), Region::Pal);
+        //Byte 12 means nothing in plain iNES
+        assert_eq!(Region::detect(&header(0, 0, 3), "game.nes"), Region::Ntsc);
+    }
+
+    #[test]
+    fn file_name_tags_for_plain_ines() {
+        assert_eq!(Region::detect(&header(0, 0, 0), "./roms/Game (E).nes"), Region::Pal);
+        assert_eq!(Region::detect(&header(0, 0, 0), "Game (Europe).nes"), Region::Pal);
+        assert_eq!(Region::detect(&header(0, 0, 0), "Game (U).nes"), Region::Ntsc);
+        assert_eq!(Region::detect(&header(0, 0, 0), "roms/Europe (PAL)/Game (U).nes"), Region::Ntsc);
+    }
+
+    #[test]
+    fn frame_rates() {
+        assert!((Region::Ntsc.frame_rate() - 60.1).abs() < 0.01);
+        assert!((Region::Pal.frame_rate() - 50.0).abs() < 0.01);
+        assert!((Region::Dendy.frame_rate() - 50.0).abs() < 0.1);
+    }
+}